@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use miadon::{message::Message, streams::{SliceReader, VecWriter}};
+
+// Decoding arbitrary bytes must fail cleanly rather than panic, and anything
+// that does decode must encode again.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(message) = Message::read_from_stream(&mut SliceReader::new(data)) {
+        let mut buffer = VecWriter::new();
+        message.write_to_stream(&mut buffer);
+    }
+});
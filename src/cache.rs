@@ -0,0 +1,886 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    mem,
+    str::FromStr,
+    sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use crate::{message::*, upstream::random_u64};
+
+
+/// How often expired records are swept out of the cache.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A rough count of the memory taken by each name in the cache, on top of
+/// its labels and records.
+const NAME_OVERHEAD: usize = 128;
+
+
+#[derive(Clone, Debug)]
+pub enum CachedData {
+    Record(ResourceRecordData),
+    /// The name exists but has no records of this type, as told by the SOA
+    /// of the zone with the given apex.
+    NoData(ResourceRecordType, Domain, StartOfAuthority),
+    /// The name does not exist, as told by the SOA of the zone with the given
+    /// apex.
+    NonExistent(Domain, StartOfAuthority),
+}
+
+impl CachedData {
+    /// Whether this is a record of type `typ`, or says there are none.
+    fn answers(&self, typ: &ResourceRecordType) -> bool {
+        match self {
+            Self::Record(data) => ResourceRecordType::from(data) == *typ,
+            Self::NoData(t, _, _) => t == typ,
+            Self::NonExistent(_, _) => true,
+        }
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct CachedRecord {
+    pub data: CachedData,
+    time_to_live: u32,
+    expires: Option<Instant>,
+}
+
+impl CachedRecord {
+    /// A record from local data, which never expires.
+    pub fn fixed(data: ResourceRecordData, time_to_live: u32) -> Self {
+        Self { data: CachedData::Record(data), time_to_live, expires: None }
+    }
+
+    /// A record learned from upstream, which expires `time_to_live` seconds
+    /// after `now`.
+    pub fn expiring(data: CachedData, time_to_live: u32, now: Instant) -> Self {
+        Self {
+            data, time_to_live,
+            expires: Some(now + Duration::from_secs(time_to_live as u64)),
+        }
+    }
+
+    pub fn is_fixed(&self) -> bool {
+        self.expires.is_none()
+    }
+
+    pub fn is_expired(&self, now: Instant) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    /// The TTL to hand out at `now`, counting down for records that expire.
+    pub fn time_to_live(&self, now: Instant) -> u32 {
+        match self.expires {
+            Some(expires) => expires.saturating_duration_since(now).as_secs() as u32,
+            None => self.time_to_live,
+        }
+    }
+
+    /// A rough count of the memory taken by the record.
+    fn estimated_size(&self) -> usize {
+        let data = match &self.data {
+            CachedData::Record(data) => data.encoded_len(),
+            CachedData::NoData(_, apex, soa) | CachedData::NonExistent(apex, soa) => {
+                apex.0.iter().map(|label| label.as_bytes().len()).sum::<usize>() + ResourceRecordData::SOA(soa.clone()).encoded_len()
+            },
+        };
+        mem::size_of::<Self>() + data
+    }
+}
+
+
+#[derive(Default)]
+pub struct Cache(HashMap<Label, CacheEntry>);
+
+impl Cache {
+    pub fn new() -> Self {
+        Self(HashMap::new())
+    }
+
+    /// Binds `records` at `domain`, walking down from the last label. Records
+    /// bound to a name that already has some are added alongside them.
+    pub fn bind(&mut self, domain: &Domain, records: Vec<CachedRecord>) {
+        if let Some(current) = self.records_mut(domain) {
+            current.extend(records);
+        }
+    }
+
+    /// Replaces the records at `domain` that were learned from upstream and
+    /// for which `stale` returns true. Records from local data are left alone.
+    pub fn replace(&mut self, domain: &Domain, records: Vec<CachedRecord>, stale: impl Fn(&CachedData) -> bool) {
+        if let Some(current) = self.records_mut(domain) {
+            current.retain(|record| record.is_fixed() || !stale(&record.data));
+            current.extend(records);
+        }
+    }
+
+    /// Finds the records at `domain`, creating the entries leading to it.
+    fn records_mut(&mut self, domain: &Domain) -> Option<&mut Vec<CachedRecord>> {
+        if domain.0.is_empty() {
+            return None;
+        }
+        let head = domain.head();
+        if domain.0.len() == 1 {
+            return match self.0.entry(head).or_insert_with(|| CacheEntry::Record(vec![])) {
+                CacheEntry::Record(records) => Some(records),
+                CacheEntry::Zone(records, _) => Some(records),
+            };
+        }
+        let entry = self.0.entry(head).or_insert_with(|| CacheEntry::Zone(vec![], Cache::new()));
+        if let CacheEntry::Record(records) = entry {
+            *entry = CacheEntry::Zone(std::mem::take(records), Cache::new());
+        }
+        match entry {
+            CacheEntry::Zone(_, zone) => zone.records_mut(&domain.tail()),
+            CacheEntry::Record(_) => None,
+        }
+    }
+
+    pub fn resolve(&self, domain: Domain) -> Vec<CachedRecord> {
+        if domain.0.is_empty() {
+            return vec![];
+        }
+        match self.0.get(&domain.head()) {
+            Some(CacheEntry::Record(records)) if domain.0.len() == 1 => records.clone(),
+            Some(CacheEntry::Zone(records, zone)) => {
+                if domain.0.len() == 1 {
+                    records.clone()
+                } else {
+                    zone.resolve(domain.tail())
+                }
+            },
+            _ => vec![],
+        }
+    }
+
+    /// Whether `domain` exists in local data: whether records are bound
+    /// locally at it or at any name below it. Records learned from upstream
+    /// do not count.
+    pub fn contains_local(&self, domain: &Domain) -> bool {
+        if domain.0.is_empty() {
+            return self.0.values().any(CacheEntry::has_local);
+        }
+        match self.0.get(&domain.head()) {
+            Some(entry) if domain.0.len() == 1 => entry.has_local(),
+            Some(CacheEntry::Zone(_, zone)) => zone.contains_local(&domain.tail()),
+            _ => false,
+        }
+    }
+
+    /// The local wildcard that stands in for `domain` when it does not exist:
+    /// `*` below its closest encloser, the longest of its ancestors that does.
+    /// See RFC 4592 section 3.3.1. Only local data is considered, so nothing
+    /// learned from upstream can hide a wildcard.
+    pub fn wildcard(&self, domain: &Domain) -> Option<Domain> {
+        let (start, wildcard) = (1..=domain.0.len()).find_map(|start| {
+            let wildcard = Domain([vec![Label::wildcard()], domain.0[start..].to_vec()].concat());
+            self.contains_local(&wildcard).then_some((start, wildcard))
+        })?;
+        // A name between the wildcard and `domain` that exists would be a
+        // closer encloser, with no wildcard of its own.
+        (0..start)
+            .all(|start| !self.contains_local(&Domain(domain.0[start..].to_vec())))
+            .then_some(wildcard)
+    }
+
+    /// Removes the records at `domain` that were learned from upstream, and
+    /// any entries left empty, returning how many records were removed.
+    pub fn remove_learned(&mut self, domain: &Domain) -> usize {
+        if domain.0.is_empty() {
+            return 0;
+        }
+        let head = domain.head();
+        let Some(entry) = self.0.get_mut(&head) else {
+            return 0;
+        };
+        let (records, mut zone) = match entry {
+            CacheEntry::Record(records) => (records, None),
+            CacheEntry::Zone(records, zone) => (records, Some(zone)),
+        };
+        let removed = if domain.0.len() == 1 {
+            let count = records.len();
+            records.retain(CachedRecord::is_fixed);
+            count - records.len()
+        } else {
+            zone.as_deref_mut().map_or(0, |zone| zone.remove_learned(&domain.tail()))
+        };
+        if records.is_empty() && zone.is_none_or(|zone| zone.0.is_empty()) {
+            self.0.remove(&head);
+        }
+        removed
+    }
+
+    /// Removes expired records, and any entries left empty, returning how
+    /// many records were removed.
+    pub fn evict_expired(&mut self, now: Instant) -> usize {
+        let mut evicted = 0;
+        self.0.retain(|_, entry| {
+            let (records, zone) = match entry {
+                CacheEntry::Record(records) => (records, None),
+                CacheEntry::Zone(records, zone) => (records, Some(zone)),
+            };
+            let count = records.len();
+            records.retain(|record| !record.is_expired(now));
+            evicted += count - records.len();
+            match zone {
+                Some(zone) => {
+                    evicted += zone.evict_expired(now);
+                    !records.is_empty() || !zone.0.is_empty()
+                },
+                None => !records.is_empty(),
+            }
+        });
+        evicted
+    }
+}
+
+pub enum CacheEntry {
+    Record(Vec<CachedRecord>),
+    Zone(Vec<CachedRecord>, Cache),
+}
+
+impl CacheEntry {
+    /// Whether records are bound locally here or anywhere below.
+    fn has_local(&self) -> bool {
+        match self {
+            Self::Record(records) => records.iter().any(CachedRecord::is_fixed),
+            Self::Zone(records, zone) => {
+                records.iter().any(CachedRecord::is_fixed) || zone.0.values().any(Self::has_local)
+            },
+        }
+    }
+}
+
+
+pub struct Answer {
+    pub response_code: ResponseCode,
+    pub records: Vec<ResourceRecord>,
+    pub authority: Vec<ResourceRecord>,
+    pub additional: Vec<ResourceRecord>,
+    /// Whether the answer comes from a zone we own.
+    pub is_authoritative: bool,
+}
+
+
+/// Tracks when each name holding learned records was last used, and roughly
+/// how much memory those records take, so the least recently used names can
+/// be evicted once the cache is over budget.
+struct Usage {
+    order: BTreeMap<u64, Domain>,
+    names: HashMap<Domain, (u64, usize)>,
+    clock: u64,
+    size: usize,
+}
+
+impl Usage {
+    fn new() -> Self {
+        Self { order: BTreeMap::new(), names: HashMap::new(), clock: 0, size: 0 }
+    }
+
+    /// Marks `name` as just used, if it is tracked.
+    fn touch(&mut self, name: &Domain) {
+        if let Some((tick, _)) = self.names.get_mut(name) {
+            let name = self.order.remove(tick).unwrap();
+            self.clock += 1;
+            *tick = self.clock;
+            self.order.insert(self.clock, name);
+        }
+    }
+
+    /// Tracks `name` as just used and holding `size` bytes of records.
+    fn insert(&mut self, name: Domain, size: usize) {
+        self.remove(&name);
+        self.clock += 1;
+        self.size += size;
+        self.order.insert(self.clock, name.clone());
+        self.names.insert(name, (self.clock, size));
+    }
+
+    fn remove(&mut self, name: &Domain) {
+        if let Some((tick, size)) = self.names.remove(name) {
+            self.order.remove(&tick);
+            self.size -= size;
+        }
+    }
+
+    /// Stops tracking the least recently used name, and returns it.
+    fn pop(&mut self) -> Option<Domain> {
+        let (_, name) = self.order.pop_first()?;
+        let (_, size) = self.names.remove(&name).unwrap();
+        self.size -= size;
+        Some(name)
+    }
+
+    /// Updates the size of every tracked name, dropping those that no longer
+    /// hold any learned records.
+    fn resize(&mut self, size_of: impl Fn(&Domain) -> usize) {
+        let mut dropped = vec![];
+        self.size = 0;
+        self.names.retain(|name, (tick, size)| {
+            *size = size_of(name);
+            self.size += *size;
+            if *size == 0 {
+                dropped.push(*tick);
+            }
+            *size != 0
+        });
+        for tick in dropped {
+            self.order.remove(&tick);
+        }
+    }
+}
+
+
+pub struct Statistics {
+    pub hits: u64,
+    pub misses: u64,
+    /// Learned records removed, whether to keep within the size limits or
+    /// because their TTL ran out.
+    pub evictions: u64,
+    /// The number of names holding learned records.
+    pub names: usize,
+    /// A rough count of the memory taken by learned records, in bytes.
+    pub size: usize,
+}
+
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, "{} hits, {} misses, {} evictions, {} names using about {} bytes",
+            self.hits, self.misses, self.evictions, self.names, self.size
+        )
+    }
+}
+
+
+/// How the records of each RRset are ordered in answers, which spreads
+/// clients across the addresses of a service.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecordOrder {
+    /// Always in the order they were bound or learned.
+    Fixed,
+    /// Rotated one place further on every answer.
+    RoundRobin,
+    /// Rotated by a random number of places.
+    Random,
+}
+
+impl FromStr for RecordOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fixed" => Ok(Self::Fixed),
+            "round-robin" => Ok(Self::RoundRobin),
+            "random" => Ok(Self::Random),
+            x => Err(format!("Unknown RRset order {x}")),
+        }
+    }
+}
+
+
+/// The cache shared by every query. Records learned from upstream are kept
+/// within `max_names` names and `max_size` bytes, evicting the least recently
+/// used names first.
+pub struct HostCache {
+    cache: RwLock<Cache>,
+    /// The apexes of the zones we are authoritative for.
+    zones: Vec<Domain>,
+    usage: Mutex<Usage>,
+    min_time_to_live: u32,
+    max_time_to_live: u32,
+    max_negative_time_to_live: u32,
+    max_names: usize,
+    max_size: usize,
+    order: RecordOrder,
+    rotation: AtomicUsize,
+    /// Whether binding an address also binds a PTR record for it.
+    generate_ptr: bool,
+    last_sweep: Mutex<Instant>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl HostCache {
+    pub fn new(
+        min_time_to_live: u32, max_time_to_live: u32, max_negative_time_to_live: u32,
+        max_names: usize, max_size: usize,
+    ) -> Self {
+        Self {
+            cache: RwLock::new(Cache::new()),
+            zones: vec![],
+            usage: Mutex::new(Usage::new()),
+            min_time_to_live, max_time_to_live,
+            max_negative_time_to_live,
+            max_names, max_size,
+            order: RecordOrder::Fixed,
+            rotation: AtomicUsize::new(0),
+            generate_ptr: false,
+            last_sweep: Mutex::new(Instant::now()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    pub fn statistics(&self) -> Statistics {
+        let usage = self.usage.lock().unwrap();
+        Statistics {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            names: usage.names.len(),
+            size: usage.size,
+        }
+    }
+
+    pub fn set_record_order(&mut self, order: RecordOrder) {
+        self.order = order;
+    }
+
+    /// Makes later calls to `bind` and `add_zone` bind a PTR record under
+    /// in-addr.arpa or ip6.arpa for every A and AAAA record.
+    pub fn set_generate_ptr(&mut self, generate_ptr: bool) {
+        self.generate_ptr = generate_ptr;
+    }
+
+    /// Binds local records, which never expire.
+    pub fn bind(&mut self, domain: Domain, time_to_live: u32, records: Vec<ResourceRecordData>) {
+        records.iter().for_each(|data| self.bind_reverse(&domain, time_to_live, data));
+        let records = records.into_iter().map(|data| CachedRecord::fixed(data, time_to_live)).collect();
+        self.cache.get_mut().unwrap().bind(&domain, records)
+    }
+
+    /// Binds a PTR record from the reverse name of an address back to `name`,
+    /// if that is enabled and there is not one already. Wildcards are left
+    /// alone, as there is no single name to point back at.
+    fn bind_reverse(&mut self, name: &Domain, time_to_live: u32, data: &ResourceRecordData) {
+        if !self.generate_ptr || name.0.first().is_some_and(Label::is_wildcard) {
+            return;
+        }
+        let reverse = match data {
+            ResourceRecordData::A(ip) => ip.reverse_name(),
+            ResourceRecordData::AAAA(ip) => ip.reverse_name(),
+            _ => return,
+        };
+        let cache = self.cache.get_mut().unwrap();
+        let exists = cache.resolve(reverse.clone()).iter().any(|record| {
+            matches!(&record.data, CachedData::Record(ResourceRecordData::PTR(target)) if target == name)
+        });
+        if !exists {
+            cache.bind(&reverse, vec![CachedRecord::fixed(ResourceRecordData::PTR(name.clone()), time_to_live)]);
+        }
+    }
+
+    /// Loads a zone we are authoritative for. Questions about names under
+    /// `apex` are answered from its records alone, and never forwarded.
+    pub fn add_zone(&mut self, apex: Domain, records: Vec<ResourceRecord>) -> Result<(), String> {
+        if let Some(record) = records.iter().find(|record| !record.name.is_subdomain_of(&apex)) {
+            return Err(format!("{} is outside the zone {apex}", record.name));
+        }
+        if !records.iter().any(|record| record.name == apex && matches!(record.data, ResourceRecordData::SOA(_))) {
+            return Err(format!("The zone {apex} has no SOA record at its apex"));
+        }
+        for record in records {
+            self.bind_reverse(&record.name, record.time_to_live, &record.data);
+            self.cache.get_mut().unwrap().bind(&record.name, vec![CachedRecord::fixed(record.data, record.time_to_live)]);
+        }
+        self.zones.push(apex);
+        Ok(())
+    }
+
+    /// The apex of the closest zone we own that holds `name`.
+    fn zone_of(&self, name: &Domain) -> Option<&Domain> {
+        self.zones.iter()
+            .filter(|apex| name.is_subdomain_of(apex))
+            .max_by_key(|apex| apex.0.len())
+    }
+
+    /// Remembers the answers in an upstream response until their TTLs run
+    /// out, clamped to the configured bounds. NXDOMAIN and NODATA responses
+    /// are remembered too, for as long as the SOA in the authority section
+    /// allows, as described in RFC 2308. If that takes the cache
+    /// over budget, the least recently used names are evicted. Records for
+    /// names outside the question's CNAME and DNAME chain are dropped, so a
+    /// response cannot plant answers for names nobody asked about, and so are
+    /// records for names in zones we own or covered by a local wildcard,
+    /// which we answer for ourselves.
+    pub fn store(&self, response: &Message) {
+        if response.flags.is_truncated() {
+            return;
+        }
+        let Some(question) = response.questions.first() else {
+            return;
+        };
+        let now = Instant::now();
+        let mut cache = self.cache.write().unwrap();
+        let names: Vec<Domain> = match response.flags.response_code() {
+            ResponseCode::NoError if !response.answers.is_empty() => {
+                let chain = Self::chain(question, response);
+                let mut rrsets: Vec<(Domain, ResourceRecordType, Vec<CachedRecord>)> = vec![];
+                for answer in response.answers.iter() {
+                    let in_chain = match answer.data {
+                        ResourceRecordData::DNAME(_) => chain.iter().any(|name| name.is_subdomain_of(&answer.name)),
+                        _ => chain.contains(&answer.name),
+                    };
+                    if !in_chain || self.is_answered_locally(&cache, &answer.name) {
+                        continue;
+                    }
+                    let typ = ResourceRecordType::from(&answer.data);
+                    let time_to_live = answer.time_to_live.clamp(self.min_time_to_live, self.max_time_to_live);
+                    let record = CachedRecord::expiring(CachedData::Record(answer.data.clone()), time_to_live, now);
+                    match rrsets.iter_mut().find(|(name, t, _)| *name == answer.name && *t == typ) {
+                        Some((_, _, records)) => records.push(record),
+                        None => rrsets.push((answer.name.clone(), typ, vec![record])),
+                    }
+                }
+                rrsets.into_iter().map(|(name, typ, records)| {
+                    cache.replace(&name, records, |data| data.answers(&typ));
+                    name
+                }).collect()
+            },
+            ResponseCode::NoError | ResponseCode::NonExistentDomain if response.answers.is_empty() => {
+                if self.is_answered_locally(&cache, &question.name) {
+                    return;
+                }
+                let Some((apex, soa, time_to_live)) = Self::negative_soa(question, response) else {
+                    return;
+                };
+                let time_to_live = time_to_live.clamp(self.min_time_to_live, self.max_negative_time_to_live);
+                if matches!(response.flags.response_code(), ResponseCode::NonExistentDomain) {
+                    let record = CachedRecord::expiring(CachedData::NonExistent(apex, soa), time_to_live, now);
+                    cache.replace(&question.name, vec![record], |_| true);
+                } else {
+                    let typ = question.typ.clone();
+                    let record = CachedRecord::expiring(CachedData::NoData(typ.clone(), apex, soa), time_to_live, now);
+                    cache.replace(&question.name, vec![record], |data| data.answers(&typ));
+                }
+                vec![question.name.clone()]
+            },
+            _ => return,
+        };
+
+        let mut usage = self.usage.lock().unwrap();
+        for name in names {
+            let size = Self::learned_size(&cache, &name);
+            usage.insert(name, size);
+        }
+        while usage.names.len() > self.max_names || usage.size > self.max_size {
+            let Some(name) = usage.pop() else {
+                break;
+            };
+            let evicted = cache.remove_learned(&name);
+            self.evictions.fetch_add(evicted as u64, Ordering::Relaxed);
+        }
+
+        let mut last_sweep = self.last_sweep.lock().unwrap();
+        if now.duration_since(*last_sweep) >= SWEEP_INTERVAL {
+            let expired = cache.evict_expired(now);
+            self.evictions.fetch_add(expired as u64, Ordering::Relaxed);
+            usage.resize(|name| Self::learned_size(&cache, name));
+            *last_sweep = now;
+        }
+    }
+
+    /// Whether `name` is answered from local data alone: it is in a zone we
+    /// own, or a local wildcard stands in for it.
+    fn is_answered_locally(&self, cache: &Cache, name: &Domain) -> bool {
+        self.zone_of(name).is_some() || cache.wildcard(name).is_some()
+    }
+
+    /// A rough count of the memory taken by the records learned for `name`,
+    /// or zero if it has none.
+    fn learned_size(cache: &Cache, name: &Domain) -> usize {
+        let records: Vec<usize> = cache.resolve(name.clone()).iter()
+            .filter(|record| !record.is_fixed())
+            .map(CachedRecord::estimated_size)
+            .collect();
+        if records.is_empty() {
+            return 0;
+        }
+        NAME_OVERHEAD + name.0.iter().map(|label| label.as_bytes().len()).sum::<usize>() + records.iter().sum::<usize>()
+    }
+
+    /// The names a response may hold answers for: the question name and
+    /// every CNAME target reached from it.
+    fn chain(question: &Question, response: &Message) -> Vec<Domain> {
+        let mut chain = vec![question.name.clone()];
+        let mut grown = true;
+        while grown {
+            grown = false;
+            for answer in response.answers.iter() {
+                if let ResourceRecordData::CNAME(target) = &answer.data {
+                    if chain.contains(&answer.name) && !chain.contains(target) {
+                        chain.push(target.clone());
+                        grown = true;
+                    }
+                }
+            }
+        }
+        chain
+    }
+
+    /// Finds the SOA in the authority section of a negative response, along
+    /// with the negative TTL it gives: the lesser of its own TTL and its
+    /// minimum field. Only an SOA for a zone above the question name counts.
+    fn negative_soa(question: &Question, response: &Message) -> Option<(Domain, StartOfAuthority, u32)> {
+        response.authoritative_records.iter().find_map(|record| match &record.data {
+            ResourceRecordData::SOA(soa) if question.name.is_subdomain_of(&record.name) => {
+                Some((record.name.clone(), soa.clone(), record.time_to_live.min(soa.minimum)))
+            },
+            _ => None,
+        })
+    }
+
+    /// Answers `question` from local data or the cache, with every record of
+    /// each RRset, ordered as configured.
+    pub fn handle_question(&self, question: &Question) -> Option<Answer> {
+        let mut answer = self.lookup(question)?;
+        self.rotate(&mut answer.records);
+        Some(answer)
+    }
+
+    /// Rotates each run of records with the same owner and type.
+    fn rotate(&self, records: &mut [ResourceRecord]) {
+        let shift = match self.order {
+            RecordOrder::Fixed => return,
+            RecordOrder::RoundRobin => self.rotation.fetch_add(1, Ordering::Relaxed),
+            RecordOrder::Random => random_u64() as usize,
+        };
+        let mut start = 0;
+        while start < records.len() {
+            let (name, typ) = (&records[start].name, ResourceRecordType::from(&records[start].data));
+            let length = records[start..].iter()
+                .take_while(|record| record.name == *name && ResourceRecordType::from(&record.data) == typ)
+                .count();
+            records[start..start + length].rotate_left(shift % length);
+            start += length;
+        }
+    }
+
+    fn lookup(&self, question: &Question) -> Option<Answer> {
+        if let Some(answer) = self.substitute(question) {
+            return Some(answer);
+        }
+        if let Some(apex) = self.zone_of(&question.name) {
+            return Some(self.authoritative_answer(question, apex));
+        }
+
+        let domain = &question.name;
+        let now = Instant::now();
+        let (records, exists_locally) = {
+            let cache = self.cache.read().unwrap();
+            match cache.wildcard(domain) {
+                Some(wildcard) => (cache.resolve(wildcard).into_iter().filter(CachedRecord::is_fixed).collect(), true),
+                None => (
+                    cache.resolve(domain.clone()).into_iter().filter(|r| !r.is_expired(now)).collect::<Vec<_>>(),
+                    cache.contains_local(domain),
+                ),
+            }
+        };
+        let answer = self.answer(question, records, exists_locally, now);
+        match answer {
+            Some(_) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                self.usage.lock().unwrap().touch(domain);
+            },
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+            },
+        }
+        answer
+    }
+
+    /// Answers from `records`, the ones at the question name or at the local
+    /// wildcard standing in for it. `exists_locally` tells whether the name
+    /// exists in local data, even with no records of its own.
+    fn answer(&self, question: &Question, records: Vec<CachedRecord>, exists_locally: bool, now: Instant) -> Option<Answer> {
+        let domain = &question.name;
+
+        // A negative answer only stands if nothing bound locally contradicts it.
+        let negative = records.iter().find_map(|r| match &r.data {
+            CachedData::NonExistent(apex, soa) => Some((ResponseCode::NonExistentDomain, apex, soa, r.time_to_live(now))),
+            CachedData::NoData(typ, apex, soa) if *typ == question.typ => Some((ResponseCode::NoError, apex, soa, r.time_to_live(now))),
+            _ => None,
+        });
+        let of_type = |typ: &ResourceRecordType| -> Vec<&CachedRecord> {
+            records.iter()
+                .filter(|r| matches!(&r.data, CachedData::Record(data) if typ.matches(&ResourceRecordType::from(data))))
+                .collect()
+        };
+        let mut matching = of_type(&question.typ);
+        if matching.is_empty() {
+            matching = of_type(&ResourceRecordType::CNAME);
+        }
+        if let Some((response_code, apex, soa, time_to_live)) = negative {
+            if matching.is_empty() && !(exists_locally && matches!(response_code, ResponseCode::NonExistentDomain)) {
+                return Some(Answer {
+                    response_code,
+                    records: vec![],
+                    authority: vec![ResourceRecord::new(apex.clone(), Class::Internet, time_to_live, ResourceRecordData::SOA(soa.clone()))],
+                    additional: vec![],
+                    is_authoritative: false,
+                });
+            }
+        }
+
+        if matching.is_empty() {
+            return None;
+        }
+        Some(Answer {
+            response_code: ResponseCode::NoError,
+            records: matching.into_iter()
+                .filter_map(|r| match &r.data {
+                    CachedData::Record(data) => Some(ResourceRecord::new(domain.clone(), Class::Internet, r.time_to_live(now), data.clone())),
+                    _ => None,
+                })
+                .collect(),
+            authority: vec![],
+            additional: vec![],
+            is_authoritative: false,
+        })
+    }
+
+    /// Answers a question about a name below a locally bound DNAME with the
+    /// DNAME and a CNAME to the rewritten name, as described in RFC 6672
+    /// section 3. If the rewritten name is too long the answer is YXDOMAIN.
+    fn substitute(&self, question: &Question) -> Option<Answer> {
+        let cache = self.cache.read().unwrap();
+        let name = &question.name;
+        let (dname, target) = (1..name.0.len()).rev().find_map(|start| {
+            let owner = Domain(name.0[start..].to_vec());
+            Self::local_records(&cache, &owner).into_iter().find_map(|record| match &record.data {
+                ResourceRecordData::DNAME(target) => {
+                    let target = Domain([&name.0[..start], &target.0[..]].concat());
+                    Some((record, target))
+                },
+                _ => None,
+            })
+        })?;
+
+        let is_authoritative = self.zone_of(&dname.name).is_some();
+        if target.is_too_long() {
+            return Some(Answer {
+                response_code: ResponseCode::NameExists,
+                records: vec![dname],
+                authority: vec![],
+                additional: vec![],
+                is_authoritative,
+            });
+        }
+        let cname = ResourceRecord::new(name.clone(), Class::Internet, dname.time_to_live, ResourceRecordData::CNAME(target));
+        Some(Answer {
+            response_code: ResponseCode::NoError,
+            records: vec![dname, cname],
+            authority: vec![],
+            additional: vec![],
+            is_authoritative,
+        })
+    }
+
+    /// Answers a question about a name in the zone at `apex`, as described in
+    /// RFC 1034 section 4.3.2. Names at or below a delegation get a referral
+    /// to its name servers, names that do not exist are answered from a
+    /// wildcard if there is one, and names or types we have no records for
+    /// get NXDOMAIN or NODATA with the zone's SOA.
+    fn authoritative_answer(&self, question: &Question, apex: &Domain) -> Answer {
+        let cache = self.cache.read().unwrap();
+        let name = &question.name;
+
+        for depth in apex.0.len() + 1..=name.0.len() {
+            let cut = Domain(name.0[name.0.len() - depth..].to_vec());
+            let servers: Vec<ResourceRecord> = Self::local_records(&cache, &cut).into_iter()
+                .filter(|record| matches!(record.data, ResourceRecordData::NS(_)))
+                .collect();
+            if !servers.is_empty() {
+                return Answer {
+                    response_code: ResponseCode::NoError,
+                    records: vec![],
+                    additional: Self::glue(&cache, &servers),
+                    authority: servers,
+                    is_authoritative: false,
+                };
+            }
+        }
+
+        let (records, exists) = match cache.wildcard(name) {
+            Some(wildcard) => {
+                let records = Self::local_records(&cache, &wildcard).into_iter()
+                    .map(|record| ResourceRecord { name: name.clone(), ..record })
+                    .collect();
+                (records, true)
+            },
+            None => (Self::local_records(&cache, name), cache.contains_local(name)),
+        };
+        let of_type = |typ: &ResourceRecordType| -> Vec<ResourceRecord> {
+            records.iter().filter(|record| typ.matches(&ResourceRecordType::from(&record.data))).cloned().collect()
+        };
+        let mut answers = of_type(&question.typ);
+        if answers.is_empty() {
+            answers = of_type(&ResourceRecordType::CNAME);
+        }
+        if !answers.is_empty() {
+            // The zone's NS RRset only goes in the authority section if it
+            // is not in the answer already, as for NS or ANY at the apex.
+            let has_servers = answers.iter().any(|record| matches!(record.data, ResourceRecordData::NS(_)));
+            let servers = if has_servers && name == apex {
+                vec![]
+            } else {
+                Self::local_records(&cache, apex).into_iter()
+                    .filter(|record| matches!(record.data, ResourceRecordData::NS(_)))
+                    .collect()
+            };
+            return Answer {
+                response_code: ResponseCode::NoError,
+                additional: Self::glue(&cache, &[answers.as_slice(), servers.as_slice()].concat()),
+                records: answers,
+                authority: servers,
+                is_authoritative: true,
+            };
+        }
+
+        // RFC 2308 section 3: the SOA goes out with the lesser of its own TTL
+        // and its minimum field.
+        let authority = Self::local_records(&cache, apex).into_iter()
+            .filter_map(|record| match &record.data {
+                ResourceRecordData::SOA(soa) => Some(ResourceRecord { time_to_live: record.time_to_live.min(soa.minimum), ..record.clone() }),
+                _ => None,
+            })
+            .collect();
+        Answer {
+            response_code: if exists { ResponseCode::NoError } else { ResponseCode::NonExistentDomain },
+            records: vec![],
+            authority,
+            additional: vec![],
+            is_authoritative: true,
+        }
+    }
+
+    /// The records bound locally at `name`.
+    fn local_records(cache: &Cache, name: &Domain) -> Vec<ResourceRecord> {
+        cache.resolve(name.clone()).into_iter()
+            .filter(CachedRecord::is_fixed)
+            .filter_map(|record| match record.data {
+                CachedData::Record(data) => Some(ResourceRecord::new(name.clone(), Class::Internet, record.time_to_live, data)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The addresses we hold for the name servers among `records`, to go in
+    /// the additional section.
+    fn glue(cache: &Cache, records: &[ResourceRecord]) -> Vec<ResourceRecord> {
+        records.iter()
+            .filter_map(|record| match &record.data {
+                ResourceRecordData::NS(server) => Some(server),
+                _ => None,
+            })
+            .flat_map(|server| Self::local_records(cache, server))
+            .filter(|record| matches!(record.data, ResourceRecordData::A(_) | ResourceRecordData::AAAA(_)))
+            .collect()
+    }
+}
@@ -0,0 +1,144 @@
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use crate::{
+    cache::RecordOrder,
+    message::{Class, Domain, ResourceRecord, ResourceRecordData},
+    upstream::SelectionPolicy,
+    zone::parse_time_to_live,
+};
+
+
+/// The TTL of a `--record` given without one.
+const DEFAULT_RECORD_TTL: u32 = 60;
+
+
+pub struct Config {
+    pub listen: SocketAddr,
+    pub upstreams: Vec<SocketAddr>,
+    pub upstream_policy: SelectionPolicy,
+    pub upstream_timeout: Duration,
+    pub upstream_retries: usize,
+    pub upstream_failure_threshold: u32,
+    pub upstream_probe_interval: Duration,
+    /// Local records given with `--record`, which never expire.
+    pub records: Vec<ResourceRecord>,
+    /// Master files to load, each with the origin its relative names are under.
+    pub zones: Vec<(Domain, PathBuf)>,
+    pub tcp_idle_timeout: Duration,
+    /// The most TCP connections served at once. Any more are closed as soon
+    /// as they are accepted.
+    pub tcp_max_connections: usize,
+    pub udp_workers: usize,
+    pub cache_min_ttl: u32,
+    pub cache_max_ttl: u32,
+    pub cache_max_negative_ttl: u32,
+    pub cache_max_names: usize,
+    pub cache_max_size: usize,
+    pub rrset_order: RecordOrder,
+    pub generate_ptr: bool,
+}
+
+impl Config {
+    /// Builds the configuration from `--name value` pairs, e.g.
+    /// `--upstream 1.1.1.1 --upstream 9.9.9.9:53 --upstream-policy fastest`.
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut config = Self {
+            listen: "0.0.0.0:8053".parse().unwrap(),
+            upstreams: vec![],
+            upstream_policy: SelectionPolicy::Failover,
+            upstream_timeout: Duration::from_secs(2),
+            upstream_retries: 1,
+            upstream_failure_threshold: 3,
+            upstream_probe_interval: Duration::from_secs(30),
+            records: vec![],
+            zones: vec![],
+            tcp_idle_timeout: Duration::from_secs(10),
+            tcp_max_connections: 128,
+            udp_workers: 16,
+            cache_min_ttl: 0,
+            cache_max_ttl: 86400,
+            cache_max_negative_ttl: 10800,
+            cache_max_names: 100_000,
+            cache_max_size: 64 * 1024 * 1024,
+            rrset_order: RecordOrder::Fixed,
+            generate_ptr: false,
+        };
+
+        let mut args = args;
+        while let Some(name) = args.next() {
+            let value = args.next().ok_or_else(|| format!("Missing value for {name}"))?;
+            match name.as_str() {
+                "--listen" => config.listen = parse_address(&value, 8053)?,
+                "--upstream" => config.upstreams.push(parse_address(&value, 53)?),
+                "--upstream-policy" => config.upstream_policy = value.parse()?,
+                "--upstream-timeout" => config.upstream_timeout = Duration::from_millis(parse_value(&name, &value)?),
+                "--upstream-retries" => config.upstream_retries = parse_value(&name, &value)?,
+                "--upstream-failures" => config.upstream_failure_threshold = parse_value(&name, &value)?,
+                "--upstream-probe-interval" => config.upstream_probe_interval = Duration::from_secs(parse_value(&name, &value)?),
+                "--tcp-idle-timeout" => config.tcp_idle_timeout = Duration::from_secs(parse_value(&name, &value)?),
+                "--tcp-max-connections" => config.tcp_max_connections = parse_value(&name, &value)?,
+                "--udp-workers" => config.udp_workers = parse_value(&name, &value)?,
+                "--cache-min-ttl" => config.cache_min_ttl = parse_value(&name, &value)?,
+                "--cache-max-ttl" => config.cache_max_ttl = parse_value(&name, &value)?,
+                "--cache-max-negative-ttl" => config.cache_max_negative_ttl = parse_value(&name, &value)?,
+                "--cache-max-names" => config.cache_max_names = parse_value(&name, &value)?,
+                "--cache-max-size" => config.cache_max_size = parse_value(&name, &value)?,
+                "--rrset-order" => config.rrset_order = value.parse()?,
+                "--generate-ptr" => config.generate_ptr = parse_value(&name, &value)?,
+                "--record" => config.records.push(parse_record(&value)?),
+                "--zone" => config.zones.push(parse_zone(&value)?),
+                _ => return Err(format!("Unknown option {name}")),
+            }
+        }
+
+        if config.cache_min_ttl > config.cache_max_ttl.min(config.cache_max_negative_ttl) {
+            return Err("--cache-min-ttl must not be larger than the maximum TTLs".into());
+        }
+        if config.tcp_max_connections == 0 {
+            return Err("--tcp-max-connections must be at least 1".into());
+        }
+        if config.udp_workers == 0 {
+            return Err("--udp-workers must be at least 1".into());
+        }
+        if config.upstreams.is_empty() {
+            config.upstreams.push("1.1.1.1:53".parse().unwrap());
+        }
+        Ok(config)
+    }
+}
+
+fn parse_address(value: &str, default_port: u16) -> Result<SocketAddr, String> {
+    value.parse::<SocketAddr>()
+        .or_else(|_| value.parse().map(|ip| SocketAddr::new(ip, default_port)))
+        .map_err(|_| format!("Invalid address {value}"))
+}
+
+/// Parses a local record given as `name [ttl] type data`, e.g.
+/// `--record "host.internal 1h TYPE65 \# 3 010203"`. The TTL is in seconds
+/// or written with units as in master files, and defaults to
+/// `DEFAULT_RECORD_TTL`.
+fn parse_record(value: &str) -> Result<ResourceRecord, String> {
+    let invalid = || format!("Invalid record {value}");
+    let (name, rest) = value.trim().split_once(char::is_whitespace).ok_or_else(invalid)?;
+    let rest = rest.trim_start();
+    let (time_to_live, rest) = match rest.split_once(char::is_whitespace) {
+        Some((ttl, rest)) if ttl.starts_with(|c: char| c.is_ascii_digit()) => (parse_time_to_live(ttl)?, rest.trim_start()),
+        _ => (DEFAULT_RECORD_TTL, rest),
+    };
+    let (typ, data) = rest.split_once(char::is_whitespace).ok_or_else(invalid)?;
+    let data = ResourceRecordData::from_generic(typ.parse()?, data.trim_start())?;
+    Ok(ResourceRecord::new(name.parse()?, Class::Internet, time_to_live, data))
+}
+
+/// Parses a zone given as `origin=path`, e.g.
+/// `--zone example.com=zones/example.com.zone`.
+fn parse_zone(value: &str) -> Result<(Domain, PathBuf), String> {
+    let Some((origin, path)) = value.split_once('=') else {
+        return Err(format!("Invalid zone {value}, expected origin=path"));
+    };
+    Ok((origin.parse()?, path.into()))
+}
+
+fn parse_value<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid value {value} for {name}"))
+}
@@ -0,0 +1,66 @@
+use std::{fmt, path::PathBuf};
+
+
+/// Everything that can go wrong while decoding a message off the wire.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecodeError {
+    /// The message ended before a field could be read.
+    Truncated,
+    /// A label length byte used one of the reserved high bit patterns.
+    BadLabelLength(u8),
+    /// A compression pointer did not point strictly before the previous one.
+    BadPointer(u16),
+    /// A name followed more compression pointers than allowed.
+    TooManyPointers,
+    /// A name was longer than 255 octets on the wire.
+    NameTooLong,
+    /// An OPT record was malformed, not owned by the root, or repeated.
+    BadOptRecord,
+    /// The RDATA length does not fit the record type.
+    BadRecordLength(u16),
+    UnknownOperation(u16),
+    UnknownResponseCode(u16),
+    /// A character string was not valid UTF-8.
+    InvalidUtf8,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "message is truncated"),
+            Self::BadLabelLength(x) => write!(f, "bad label length {x:#04x}"),
+            Self::BadPointer(x) => write!(f, "bad compression pointer to offset {x}"),
+            Self::TooManyPointers => write!(f, "too many compression pointers in name"),
+            Self::NameTooLong => write!(f, "name is longer than 255 octets"),
+            Self::BadOptRecord => write!(f, "bad OPT record"),
+            Self::BadRecordLength(x) => write!(f, "bad record data length {x}"),
+            Self::UnknownOperation(x) => write!(f, "unknown operation with code {x}"),
+            Self::UnknownResponseCode(x) => write!(f, "unknown response code {x}"),
+            Self::InvalidUtf8 => write!(f, "invalid UTF-8 in string"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+
+/// A problem found while loading a zone from a master file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ZoneError {
+    pub path: PathBuf,
+    /// The line the offending entry starts on, counting from one, if the
+    /// problem lies with an entry rather than the file as a whole.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ZoneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{line}: {}", self.path.display(), self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+impl std::error::Error for ZoneError {}
@@ -0,0 +1,10 @@
+pub mod cache;
+pub mod config;
+pub mod error;
+pub mod message;
+pub mod server;
+pub mod streams;
+pub mod tcp;
+pub mod udp;
+pub mod upstream;
+pub mod zone;
@@ -1,127 +1,70 @@
+use std::{net::{TcpListener, UdpSocket}, process, sync::Arc, thread, time::Duration};
 
-mod message;
-mod streams;
-mod udp;
+use miadon::{
+    cache::HostCache,
+    config::Config,
+    message::*,
+    server::Server,
+    upstream::{Upstream, UpstreamPool},
+    zone::Zone,
+};
 
-use std::{net::{UdpSocket, SocketAddr}, collections::HashMap, str::FromStr};
 
-use crate::{message::*, udp::UDPStream};
+/// How often the cache statistics are logged.
+const STATISTICS_INTERVAL: Duration = Duration::from_secs(60);
 
 
-struct Cache(HashMap<String, CacheEntry>);
-
-impl Cache {
-    fn new() -> Self {
-        Self(HashMap::new())
-    }
-
-    fn bind(&mut self, domain: Domain, entry: CacheEntry) {
-        if domain.0.len() == 1 {
-            self.0.insert(domain.head().clone(), entry);
-            return;
-        }
-        if let Some(current_entry) = self.0.get(&domain.head()) {
-            match current_entry {
-                CacheEntry::Record(records) => {
-                    let mut cache = Cache::new();
-                    cache.bind(domain.tail(), entry);
-                    self.0.insert(domain.head(), CacheEntry::Zone(records.clone(), cache));
-                },
-                CacheEntry::Zone(_, _) => {
-                    let cache_entry = self.0.get_mut(&domain.head()).unwrap();
-                    match cache_entry {
-                        CacheEntry::Zone(_, zone) => zone.bind(domain.tail(), entry),
-                        _ => panic!("Invalid state")
-                    }
-                },
-            }
-        } else {
-            let mut cache = Cache::new();
-            cache.bind(Domain(domain.0[1..].to_vec()), entry);
-            self.0.insert(domain.0[0].clone(), CacheEntry::Zone(vec![], cache));
-        }
-    }
-
-    fn resolve(&self, domain: Domain) -> Vec<ResourceRecordData> {
-        let domain = domain.0.clone();
-        if let Some(entry) = self.0.get(&domain[0]) {
-            match entry {
-                CacheEntry::Record(records) => records.clone(),
-                CacheEntry::Zone(ip, zone) => {
-                    if domain.len() == 1 {
-                        ip.clone()
-                    } else {
-                        let rest = Domain(domain[1..].to_vec());
-                        zone.resolve(rest)
-                    }
-                }
-            }
-        } else {
-            vec![]
-        }
-    }
-}
-
-enum CacheEntry {
-    Record(Vec<ResourceRecordData>),
-    Zone(Vec<ResourceRecordData>, Cache),
-}
-
-struct HostCache {
-    cache: Cache,
-}
-
-impl HostCache {
-    fn new() -> Self {
-        Self { cache: Cache::new() }
-    }
-
-    fn bind(&mut self, domain: Domain, entry: CacheEntry) {
-        self.cache.bind(domain, entry)
+fn main() {
+    let config = Config::from_args(std::env::args().skip(1)).unwrap_or_else(|error| {
+        eprintln!("{error}");
+        process::exit(2);
+    });
+
+    let upstream = UpstreamPool::new(
+        config.upstreams.iter()
+            .map(|&address| Upstream::new(address, config.upstream_timeout, config.upstream_retries))
+            .collect(),
+        config.upstream_policy,
+        config.upstream_failure_threshold,
+        config.upstream_probe_interval,
+    );
+
+    let mut cache = HostCache::new(
+        config.cache_min_ttl, config.cache_max_ttl, config.cache_max_negative_ttl,
+        config.cache_max_names, config.cache_max_size,
+    );
+    cache.set_record_order(config.rrset_order);
+    cache.set_generate_ptr(config.generate_ptr);
+    cache.bind("localhost".parse().unwrap(), 60, vec![
+        ResourceRecordData::A(IPV4(127, 0, 0, 1)),
+        ResourceRecordData::AAAA(IPV6([0, 0, 0, 0, 0, 0, 0, 1])),
+    ]);
+    for record in config.records {
+        cache.bind(record.name, record.time_to_live, vec![record.data]);
     }
-
-    fn handle_question(&self, question: &Question) -> Option<ResourceRecord> {
-        let domain = &question.name;
-        let records = self.cache.resolve(domain.clone());
-        records
-            .iter()
-            .filter(|r| question.typ == r.clone().into())
-            .next()
-            .map(|r| ResourceRecord::new(domain.clone(), Class::Internet, 60, r.clone()))
+    for (origin, path) in config.zones {
+        let zone = Zone::load(&path, origin.clone()).unwrap_or_else(|error| {
+            eprintln!("{error}");
+            process::exit(2);
+        });
+        cache.add_zone(origin, zone.records).unwrap_or_else(|error| {
+            eprintln!("{}: {error}", path.display());
+            process::exit(2);
+        });
     }
-}
 
+    let server = Arc::new(Server::new(cache, upstream, config.tcp_idle_timeout, config.tcp_max_connections));
 
-fn main() {
-    let socket = UdpSocket::bind("0.0.0.0:8053").expect("Failed to bind socket");
+    let listener = TcpListener::bind(config.listen).expect("Failed to bind TCP socket");
+    let socket = UdpSocket::bind(config.listen).expect("Failed to bind UDP socket");
     println!("Server listening on {}", socket.local_addr().unwrap());
-    let mut stream = UDPStream::new(socket);
-
-    let mut cache = HostCache::new();
-    cache.bind(Domain(vec!["localhost".into()]), CacheEntry::Record(vec![ResourceRecordData::A(IPV4(127, 0, 0, 1))]));
-    cache.bind(Domain(vec!["google".into(), "com".into()]), CacheEntry::Record(vec![ResourceRecordData::A(IPV4(142, 250, 187, 206))]));
 
-    loop {
-        let message = Message::read_from_stream(&mut stream);
-        let answers: Vec<_> = message.questions.iter().map(|q| cache.handle_question(q)).filter_map(|x| x).collect();
-        let response = if answers.len() > 0 {
-            Message::new(
-                message.id,
-                Flags::new(
-                    false, true, false, false, false,
-                    Operation::Query, ResponseCode::NoError
-                ),
-                vec![], answers, vec![], vec![])
-        } else {
-            let mut upstream = UDPStream::new(UdpSocket::bind("0.0.0.0:0").unwrap());
-            let destination = "1.1.1.1:53";
-            upstream.set_target(SocketAddr::from_str(destination).unwrap());
-            message.write_to_stream(&mut upstream);
-            let response = Message::read_from_stream(&mut stream).with_id(message.id);
-            
-        };
-        response.write_to_stream(&mut stream);
-        stream.flush();
-    }
+    let tcp_server = server.clone();
+    thread::spawn(move || tcp_server.serve_tcp(listener));
+    let statistics_server = server.clone();
+    thread::spawn(move || loop {
+        thread::sleep(STATISTICS_INTERVAL);
+        println!("Cache: {}", statistics_server.statistics());
+    });
+    server.serve_udp(socket, config.udp_workers);
 }
-
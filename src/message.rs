@@ -1,16 +1,18 @@
-use crate::streams::{IStream, OStream};
+use std::{collections::HashMap, fmt, hash::{Hash, Hasher}, mem, str::FromStr};
+
+use crate::{error::DecodeError, streams::{IStream, OStream, SliceReader, VecWriter}};
 
 
 #[derive(Clone, Debug)]
 pub struct IPV4(pub u8, pub u8, pub u8, pub u8);
 
 impl IPV4 {
-    pub fn read_from_stream(stream: &mut dyn IStream) -> Self {
-        let p0 = stream.read_u8();
-        let p1 = stream.read_u8();
-        let p2 = stream.read_u8();
-        let p3 = stream.read_u8();
-        Self(p0, p1, p2, p3)
+    pub fn read_from_stream(stream: &mut dyn IStream) -> Result<Self, DecodeError> {
+        let p0 = stream.read_u8()?;
+        let p1 = stream.read_u8()?;
+        let p2 = stream.read_u8()?;
+        let p3 = stream.read_u8()?;
+        Ok(Self(p0, p1, p2, p3))
     }
 
     pub fn write_to_stream(&self, stream: &mut dyn OStream) {
@@ -19,13 +21,128 @@ impl IPV4 {
         stream.write_u8(self.2);
         stream.write_u8(self.3);
     }
+
+    /// The name under in-addr.arpa for reverse lookups of this address.
+    pub fn reverse_name(&self) -> Domain {
+        format!("{}.{}.{}.{}.in-addr.arpa", self.3, self.2, self.1, self.0).parse().unwrap()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct IPV6(pub [u16; 8]);
+
+impl IPV6 {
+    pub fn read_from_stream(stream: &mut dyn IStream) -> Result<Self, DecodeError> {
+        let mut segments = [0u16; 8];
+        for segment in segments.iter_mut() {
+            *segment = stream.read_u16()?;
+        }
+        Ok(Self(segments))
+    }
+
+    pub fn write_to_stream(&self, stream: &mut dyn OStream) {
+        self.0.iter().for_each(|&segment| stream.write_u16(segment));
+    }
+
+    /// The name under ip6.arpa for reverse lookups of this address, with one
+    /// label per nibble, least significant first.
+    pub fn reverse_name(&self) -> Domain {
+        let nibbles: Vec<String> = self.0.iter().rev()
+            .flat_map(|segment| (0..4).map(move |nibble| format!("{:x}", (segment >> (nibble * 4)) & 0xf)))
+            .collect();
+        format!("{}.ip6.arpa", nibbles.join(".")).parse().unwrap()
+    }
 }
 
+
+/// The most compression pointers a single name may follow.
+const MAX_POINTERS: usize = 32;
+
+/// The longest a single label may be.
+const MAX_LABEL_LENGTH: usize = 63;
+
+/// The longest a name may be on the wire, including length bytes.
+const MAX_NAME_LENGTH: usize = 255;
+
+/// Compression pointers can only address the first 16KiB of a message.
+const MAX_POINTER_OFFSET: usize = 0x3fff;
+
+/// The offsets of names already written to a message, so that later names can
+/// point back at them instead of being written out again.
+#[derive(Default)]
+pub struct Names(HashMap<Vec<Label>, u16>);
+
+/// One label of a domain name, kept as the raw bytes it was given as. Labels
+/// compare and hash without regard to ASCII case, as RFC 4343 requires, but
+/// keep their case for output.
 #[derive(Clone, Debug)]
-pub struct Domain(pub Vec<String>);
+pub struct Label(Vec<u8>);
+
+impl Label {
+    pub fn new(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.is_empty() || bytes.len() > MAX_LABEL_LENGTH {
+            return Err(format!("Label length {} is not between 1 and {MAX_LABEL_LENGTH}", bytes.len()));
+        }
+        Ok(Self(bytes.to_vec()))
+    }
+
+    /// The `*` label that makes a name a wildcard.
+    pub fn wildcard() -> Self {
+        Self(vec![b'*'])
+    }
+
+    pub fn is_wildcard(&self) -> bool {
+        self.0 == b"*"
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl PartialEq for Label {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq_ignore_ascii_case(&other.0)
+    }
+}
+
+impl Eq for Label {}
+
+impl Hash for Label {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.0.len());
+        self.0.iter().for_each(|byte| state.write_u8(byte.to_ascii_lowercase()));
+    }
+}
+
+impl fmt::Display for Label {
+    /// Writes the label in presentation format, escaping characters that
+    /// would otherwise be read as syntax and writing anything that is not
+    /// printable ASCII as `\DDD`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|&byte| match byte {
+            b'.' | b'\\' | b'"' | b'(' | b')' | b';' | b'@' | b'$' => write!(f, "\\{}", byte as char),
+            0x21..=0x7e => write!(f, "{}", byte as char),
+            _ => write!(f, "\\{byte:03}"),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Domain(pub Vec<Label>);
 
 impl Domain {
-    pub fn head(&self) -> String  {
+    /// Builds a name from its labels, leftmost first, rejecting it if it would
+    /// take more than 255 octets on the wire.
+    pub fn new(labels: Vec<Label>) -> Result<Self, String> {
+        let domain = Self(labels);
+        if domain.is_too_long() {
+            return Err(format!("Domain name {domain} is too long"));
+        }
+        Ok(domain)
+    }
+
+    pub fn head(&self) -> Label {
         self.0.last().unwrap().clone()
     }
 
@@ -33,105 +150,492 @@ impl Domain {
         Self(self.0[..self.0.len()-1].to_vec())
     }
 
-    pub fn read_from_stream(stream: &mut dyn IStream) -> Self {
-        let mut parts: Vec<String> = Vec::new();
-        let mut size: u8;
-        while ({size = stream.read_u8(); size}) > 0 {
-            parts.push(stream.read_string(size as usize));
+    /// Whether the name would take more than 255 octets on the wire.
+    pub fn is_too_long(&self) -> bool {
+        self.0.iter().map(|label| label.0.len() + 1).sum::<usize>() + 1 > MAX_NAME_LENGTH
+    }
+
+    /// Whether this name is `other` or lies below it.
+    pub fn is_subdomain_of(&self, other: &Domain) -> bool {
+        self.0.ends_with(&other.0)
+    }
+
+    /// Reads a name, following compression pointers back into the message.
+    /// Each pointer must point strictly before the previous one, which rules
+    /// out loops.
+    pub fn read_from_stream(stream: &mut dyn IStream) -> Result<Self, DecodeError> {
+        let mut parts = Vec::new();
+        let mut length = 1;
+        let mut resume_at = None;
+        let mut last_pointer = stream.position();
+        let mut pointers = 0;
+        loop {
+            let size = stream.read_u8()?;
+            match size & 0xc0 {
+                0x00 if size == 0 => break,
+                0x00 => {
+                    length += size as usize + 1;
+                    if length > MAX_NAME_LENGTH {
+                        return Err(DecodeError::NameTooLong);
+                    }
+                    parts.push(Label(stream.read_bytes(size as usize)?));
+                },
+                0xc0 => {
+                    let offset = ((size as u16 & 0x3f) << 8) | stream.read_u8()? as u16;
+                    if offset as usize >= last_pointer {
+                        return Err(DecodeError::BadPointer(offset));
+                    }
+                    pointers += 1;
+                    if pointers > MAX_POINTERS {
+                        return Err(DecodeError::TooManyPointers);
+                    }
+                    if resume_at.is_none() {
+                        resume_at = Some(stream.position());
+                    }
+                    last_pointer = offset as usize;
+                    stream.seek(last_pointer)?;
+                },
+                _ => return Err(DecodeError::BadLabelLength(size)),
+            }
         };
-        Self(parts)
+        if let Some(position) = resume_at {
+            stream.seek(position)?;
+        }
+        Ok(Self(parts))
     }
 
-    pub fn write_to_stream(&self, stream: &mut dyn OStream) {
-        for part in self.0.clone() {
-            stream.write_u8(part.len() as u8);
-            stream.write_string(part);
+    /// Writes the name, replacing the longest suffix that has already been
+    /// written with a pointer to it.
+    pub fn write_to_stream(&self, stream: &mut dyn OStream, names: &mut Names) {
+        for (i, part) in self.0.iter().enumerate() {
+            let suffix = self.0[i..].to_vec();
+            if let Some(&offset) = names.0.get(&suffix) {
+                stream.write_u16(0xc000 | offset);
+                return;
+            }
+            let position = stream.position();
+            if position <= MAX_POINTER_OFFSET {
+                names.0.insert(suffix, position as u16);
+            }
+            stream.write_u8(part.0.len() as u8);
+            stream.write_bytes(&part.0);
+        }
+        stream.write_u8(0);
+    }
+
+    pub fn write_uncompressed(&self, stream: &mut dyn OStream) {
+        for part in self.0.iter() {
+            stream.write_u8(part.0.len() as u8);
+            stream.write_bytes(&part.0);
         }
         stream.write_u8(0);
     }
 }
 
+impl fmt::Display for Domain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, ".");
+        }
+        self.0.iter().try_for_each(|label| write!(f, "{label}."))
+    }
+}
+
+impl FromStr for Domain {
+    type Err = String;
+
+    /// Parses a name in presentation format, where `\X` stands for the
+    /// character X and `\DDD` for the byte with decimal value DDD. Anything
+    /// outside ASCII must be escaped. The trailing dot is optional and a lone
+    /// `.` is the root.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "." {
+            return Ok(Self(vec![]));
+        }
+        let invalid = |reason: String| format!("Invalid domain name {s}: {reason}");
+        let mut labels = vec![];
+        let mut label = vec![];
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '.' => labels.push(Label::new(&mem::take(&mut label)).map_err(invalid)?),
+                '\\' => match chars.next() {
+                    Some(c) if c.is_ascii_digit() => {
+                        let digits: String = [Some(c), chars.next(), chars.next()].into_iter().flatten().collect();
+                        let byte = digits.parse().ok()
+                            .filter(|_| digits.len() == 3)
+                            .ok_or_else(|| invalid(format!("bad escape \\{digits}")))?;
+                        label.push(byte);
+                    },
+                    Some(c) if c.is_ascii() => label.push(c as u8),
+                    Some(c) => return Err(invalid(format!("{c} is not ASCII"))),
+                    None => return Err(invalid("dangling escape".into())),
+                },
+                c if c.is_ascii() => label.push(c as u8),
+                c => return Err(invalid(format!("{c} is not ASCII"))),
+            }
+        }
+        if !label.is_empty() {
+            labels.push(Label::new(&label).map_err(invalid)?);
+        }
+        Self::new(labels)
+    }
+}
+
 
 #[derive(Clone, Debug, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum ResourceRecordType {
     A,
+    NS,
+    CNAME,
+    SOA,
+    PTR,
+    MX,
+    TXT,
+    AAAA,
+    SRV,
+    DNAME,
     Options,
+    /// Only asked for in questions, where it stands for every type.
+    Any,
+    CAA,
+    Unknown(u16),
 }
 
-impl Into<u8> for ResourceRecordType {
-    fn into(self) -> u8 {
-        match self {
-            Self::A => 1,
-            Self::Options => 41,
+impl ResourceRecordType {
+    /// Whether records of type `typ` answer a question for this type, as
+    /// they all do for ANY.
+    pub fn matches(&self, typ: &ResourceRecordType) -> bool {
+        *self == ResourceRecordType::Any || self == typ
+    }
+}
+
+impl From<ResourceRecordType> for u16 {
+    fn from(typ: ResourceRecordType) -> Self {
+        match typ {
+            ResourceRecordType::A => 1,
+            ResourceRecordType::NS => 2,
+            ResourceRecordType::CNAME => 5,
+            ResourceRecordType::SOA => 6,
+            ResourceRecordType::PTR => 12,
+            ResourceRecordType::MX => 15,
+            ResourceRecordType::TXT => 16,
+            ResourceRecordType::AAAA => 28,
+            ResourceRecordType::SRV => 33,
+            ResourceRecordType::DNAME => 39,
+            ResourceRecordType::Options => 41,
+            ResourceRecordType::Any => 255,
+            ResourceRecordType::CAA => 257,
+            ResourceRecordType::Unknown(x) => x,
         }
     }
 }
 
-impl Into<u16> for ResourceRecordType {
-    fn into(self) -> u16 {
-        Into::<u8>::into(self) as u16
+impl From<u16> for ResourceRecordType {
+    fn from(x: u16) -> Self {
+        match x {
+            1 => ResourceRecordType::A,
+            2 => ResourceRecordType::NS,
+            5 => ResourceRecordType::CNAME,
+            6 => ResourceRecordType::SOA,
+            12 => ResourceRecordType::PTR,
+            15 => ResourceRecordType::MX,
+            16 => ResourceRecordType::TXT,
+            28 => ResourceRecordType::AAAA,
+            33 => ResourceRecordType::SRV,
+            39 => ResourceRecordType::DNAME,
+            41 => ResourceRecordType::Options,
+            255 => ResourceRecordType::Any,
+            257 => ResourceRecordType::CAA,
+            x => ResourceRecordType::Unknown(x),
+        }
     }
 }
 
-impl Into<ResourceRecordType> for u8 {
-    fn into(self) -> ResourceRecordType {
-        (self as u16).into()
+impl FromStr for ResourceRecordType {
+    type Err = String;
+
+    /// Parses a type mnemonic, or the generic `TYPEnnn` form from RFC 3597.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_uppercase();
+        Ok(match s.as_str() {
+            "A" => Self::A,
+            "NS" => Self::NS,
+            "CNAME" => Self::CNAME,
+            "SOA" => Self::SOA,
+            "PTR" => Self::PTR,
+            "MX" => Self::MX,
+            "TXT" => Self::TXT,
+            "AAAA" => Self::AAAA,
+            "SRV" => Self::SRV,
+            "DNAME" => Self::DNAME,
+            "OPT" => Self::Options,
+            "ANY" => Self::Any,
+            "CAA" => Self::CAA,
+            _ => s.strip_prefix("TYPE")
+                .and_then(|code| code.parse::<u16>().ok())
+                .map(Self::from)
+                .ok_or_else(|| format!("Unknown resource record type {s}"))?,
+        })
     }
 }
 
-impl Into<ResourceRecordType> for u16 {
-    fn into(self) -> ResourceRecordType {
-        match self {
-            1 => ResourceRecordType::A,
-            41 => ResourceRecordType::Options,
-            x => panic!("Unknown resource record type {x}"),
+
+#[derive(Clone, Debug)]
+pub struct StartOfAuthority {
+    pub primary: Domain,
+    pub mailbox: Domain,
+    pub serial: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    pub minimum: u32,
+}
+
+impl StartOfAuthority {
+    pub fn read_from_stream(stream: &mut dyn IStream) -> Result<Self, DecodeError> {
+        Ok(Self {
+            primary: Domain::read_from_stream(stream)?,
+            mailbox: Domain::read_from_stream(stream)?,
+            serial: stream.read_u32()?,
+            refresh: stream.read_u32()?,
+            retry: stream.read_u32()?,
+            expire: stream.read_u32()?,
+            minimum: stream.read_u32()?,
+        })
+    }
+
+    pub fn write_to_stream(&self, stream: &mut dyn OStream, names: &mut Names) {
+        self.primary.write_to_stream(stream, names);
+        self.mailbox.write_to_stream(stream, names);
+        stream.write_u32(self.serial);
+        stream.write_u32(self.refresh);
+        stream.write_u32(self.retry);
+        stream.write_u32(self.expire);
+        stream.write_u32(self.minimum);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MailExchange {
+    pub preference: u16,
+    pub exchange: Domain,
+}
+
+impl MailExchange {
+    pub fn read_from_stream(stream: &mut dyn IStream) -> Result<Self, DecodeError> {
+        Ok(Self {
+            preference: stream.read_u16()?,
+            exchange: Domain::read_from_stream(stream)?,
+        })
+    }
+
+    pub fn write_to_stream(&self, stream: &mut dyn OStream, names: &mut Names) {
+        stream.write_u16(self.preference);
+        self.exchange.write_to_stream(stream, names);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Service {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: Domain,
+}
+
+impl Service {
+    pub fn read_from_stream(stream: &mut dyn IStream) -> Result<Self, DecodeError> {
+        Ok(Self {
+            priority: stream.read_u16()?,
+            weight: stream.read_u16()?,
+            port: stream.read_u16()?,
+            target: Domain::read_from_stream(stream)?,
+        })
+    }
+
+    /// The target is never compressed, as RFC 2782 forbids it.
+    pub fn write_to_stream(&self, stream: &mut dyn OStream) {
+        stream.write_u16(self.priority);
+        stream.write_u16(self.weight);
+        stream.write_u16(self.port);
+        self.target.write_uncompressed(stream);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CertificationAuthority {
+    pub flags: u8,
+    pub tag: String,
+    pub value: Vec<u8>,
+}
+
+impl CertificationAuthority {
+    pub fn read_from_stream(stream: &mut dyn IStream, size: usize) -> Result<Self, DecodeError> {
+        let flags = stream.read_u8()?;
+        let tag_length = stream.read_u8()? as usize;
+        if tag_length + 2 > size {
+            return Err(DecodeError::BadRecordLength(size as u16));
         }
+        Ok(Self {
+            flags,
+            tag: stream.read_string(tag_length)?,
+            value: stream.read_bytes(size - tag_length - 2)?,
+        })
+    }
+
+    pub fn write_to_stream(&self, stream: &mut dyn OStream) {
+        stream.write_u8(self.flags);
+        stream.write_u8(self.tag.len() as u8);
+        stream.write_string(&self.tag);
+        stream.write_bytes(&self.value);
     }
 }
 
+
 #[derive(Clone, Debug)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum ResourceRecordData {
     A(IPV4),
+    NS(Domain),
+    CNAME(Domain),
+    SOA(StartOfAuthority),
+    PTR(Domain),
+    MX(MailExchange),
+    TXT(Vec<Vec<u8>>),
+    AAAA(IPV6),
+    SRV(Service),
+    /// Redirects every name below the owner to the same name below the
+    /// target, as described in RFC 6672.
+    DNAME(Domain),
     Options(Vec<u8>),
+    CAA(CertificationAuthority),
+    /// Data for a type we do not understand, or given in the generic
+    /// `\# len hex` syntax, kept as the raw RDATA along with its type code.
+    Unknown(u16, Vec<u8>),
 }
 
 impl ResourceRecordData {
-    pub fn read_from_stream(stream: &mut dyn IStream, typ: &ResourceRecordType) -> Self {
-        let size = stream.read_u16();
-        match typ {
-            ResourceRecordType::A => Self::A(IPV4::read_from_stream(stream)),
-            ResourceRecordType::Options => Self::Options(stream.read_bytes(size as usize)),
+    /// Parses RDATA written in the generic `\# len hex` syntax from RFC 3597.
+    /// Data for a type we know is decoded as it would be off the wire, so it
+    /// must be valid for that type.
+    pub fn from_generic(typ: ResourceRecordType, text: &str) -> Result<Self, String> {
+        let mut parts = text.split_whitespace();
+        if parts.next() != Some("\\#") {
+            return Err(format!("Expected generic record data, found {text}"));
+        }
+        let length: usize = parts.next()
+            .and_then(|length| length.parse().ok())
+            .ok_or_else(|| format!("Invalid length in record data {text}"))?;
+        let hex: String = parts.collect();
+        if hex.len() != length * 2 {
+            return Err(format!("Record data {text} is not {length} bytes long"));
         }
+        let length = u16::try_from(length).map_err(|_| format!("Record data {text} is too long"))?;
+        if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(format!("Invalid hex in record data {text}"));
+        }
+        let bytes: Vec<u8> = (0..length as usize)
+            .map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap())
+            .collect();
+        if matches!(typ, ResourceRecordType::Unknown(_)) {
+            return Ok(Self::Unknown(typ.into(), bytes));
+        }
+        let wire = [&length.to_be_bytes()[..], &bytes].concat();
+        Self::read_from_stream(&mut SliceReader::new(&wire), &typ)
+            .map_err(|error| format!("Invalid record data {text}: {error}"))
     }
 
-    pub fn write_to_stream(&self, stream: &mut dyn OStream) {
-        stream.write_u16(self.len() as u16);
-        match self {
-            Self::A(ip) => ip.write_to_stream(stream),
-            Self::Options(options) => stream.write_bytes(options.clone()),
+    pub fn read_from_stream(stream: &mut dyn IStream, typ: &ResourceRecordType) -> Result<Self, DecodeError> {
+        let size = stream.read_u16()?;
+        let start = stream.position();
+        let data = match typ {
+            ResourceRecordType::A => Self::A(IPV4::read_from_stream(stream)?),
+            ResourceRecordType::NS => Self::NS(Domain::read_from_stream(stream)?),
+            ResourceRecordType::CNAME => Self::CNAME(Domain::read_from_stream(stream)?),
+            ResourceRecordType::SOA => Self::SOA(StartOfAuthority::read_from_stream(stream)?),
+            ResourceRecordType::PTR => Self::PTR(Domain::read_from_stream(stream)?),
+            ResourceRecordType::MX => Self::MX(MailExchange::read_from_stream(stream)?),
+            ResourceRecordType::TXT => {
+                let mut strings = vec![];
+                while stream.position() < start + size as usize {
+                    let length = stream.read_u8()?;
+                    strings.push(stream.read_bytes(length as usize)?);
+                }
+                Self::TXT(strings)
+            },
+            ResourceRecordType::AAAA => Self::AAAA(IPV6::read_from_stream(stream)?),
+            ResourceRecordType::SRV => Self::SRV(Service::read_from_stream(stream)?),
+            ResourceRecordType::DNAME => Self::DNAME(Domain::read_from_stream(stream)?),
+            ResourceRecordType::Options => Self::Options(stream.read_bytes(size as usize)?),
+            ResourceRecordType::Any => Self::Unknown(255, stream.read_bytes(size as usize)?),
+            ResourceRecordType::CAA => Self::CAA(CertificationAuthority::read_from_stream(stream, size as usize)?),
+            ResourceRecordType::Unknown(x) => Self::Unknown(*x, stream.read_bytes(size as usize)?),
         };
+        if stream.position() != start + size as usize {
+            return Err(DecodeError::BadRecordLength(size));
+        }
+        Ok(data)
     }
 
-    fn len(&self) -> usize {
+    /// Writes the length-prefixed RDATA. Names inside it are only compressed
+    /// for the types RFC 3597 allows.
+    pub fn write_to_stream(&self, stream: &mut dyn OStream, names: &mut Names) {
+        let mut buffer = VecWriter::starting_at(stream.position() + 2);
         match self {
-            Self::A(_) => 4,
-            Self::Options(options) => options.len(),
-        }
+            Self::A(ip) => ip.write_to_stream(&mut buffer),
+            Self::NS(name) => name.write_to_stream(&mut buffer, names),
+            Self::CNAME(name) => name.write_to_stream(&mut buffer, names),
+            Self::SOA(soa) => soa.write_to_stream(&mut buffer, names),
+            Self::PTR(name) => name.write_to_stream(&mut buffer, names),
+            Self::MX(mx) => mx.write_to_stream(&mut buffer, names),
+            Self::TXT(strings) => strings.iter().for_each(|string| {
+                buffer.write_u8(string.len() as u8);
+                buffer.write_bytes(string);
+            }),
+            Self::AAAA(ip) => ip.write_to_stream(&mut buffer),
+            Self::SRV(srv) => srv.write_to_stream(&mut buffer),
+            Self::DNAME(name) => name.write_uncompressed(&mut buffer),
+            Self::Options(options) => buffer.write_bytes(options),
+            Self::CAA(caa) => caa.write_to_stream(&mut buffer),
+            Self::Unknown(_, data) => buffer.write_bytes(data),
+        };
+        stream.write_u16(buffer.bytes().len() as u16);
+        stream.write_bytes(buffer.bytes());
+    }
+
+    /// The size of the RDATA once encoded on its own.
+    pub fn encoded_len(&self) -> usize {
+        let mut buffer = VecWriter::new();
+        self.write_to_stream(&mut buffer, &mut Names::default());
+        buffer.bytes().len() - 2
     }
 }
 
-impl Into<ResourceRecordType> for &ResourceRecordData {
-    fn into(self) -> ResourceRecordType {
-        match self {
+impl From<&ResourceRecordData> for ResourceRecordType {
+    fn from(data: &ResourceRecordData) -> Self {
+        match data {
             ResourceRecordData::A(_) => ResourceRecordType::A,
+            ResourceRecordData::NS(_) => ResourceRecordType::NS,
+            ResourceRecordData::CNAME(_) => ResourceRecordType::CNAME,
+            ResourceRecordData::SOA(_) => ResourceRecordType::SOA,
+            ResourceRecordData::PTR(_) => ResourceRecordType::PTR,
+            ResourceRecordData::MX(_) => ResourceRecordType::MX,
+            ResourceRecordData::TXT(_) => ResourceRecordType::TXT,
+            ResourceRecordData::AAAA(_) => ResourceRecordType::AAAA,
+            ResourceRecordData::SRV(_) => ResourceRecordType::SRV,
+            ResourceRecordData::DNAME(_) => ResourceRecordType::DNAME,
             ResourceRecordData::Options(_) => ResourceRecordType::Options,
+            ResourceRecordData::CAA(_) => ResourceRecordType::CAA,
+            ResourceRecordData::Unknown(x, _) => (*x).into(),
         }
     }
 }
 
-impl Into<ResourceRecordType> for ResourceRecordData {
-    fn into(self) -> ResourceRecordType {
-        (&self).into()
+impl From<ResourceRecordData> for ResourceRecordType {
+    fn from(data: ResourceRecordData) -> Self {
+        (&data).into()
     }
 }
 
@@ -159,15 +663,19 @@ pub enum ResponseCode {
     BadCookie,
 }
 
-impl Into<ResponseCode> for u8 {
-    fn into(self) -> ResponseCode {
-        (self as u16).into()
+impl TryFrom<u8> for ResponseCode {
+    type Error = DecodeError;
+
+    fn try_from(x: u8) -> Result<Self, Self::Error> {
+        (x as u16).try_into()
     }
 }
 
-impl Into<ResponseCode> for u16 {
-    fn into(self) -> ResponseCode {
-        match self {
+impl TryFrom<u16> for ResponseCode {
+    type Error = DecodeError;
+
+    fn try_from(x: u16) -> Result<Self, Self::Error> {
+        Ok(match x {
             0 => ResponseCode::NoError,
             1 => ResponseCode::FormatError,
             2 => ResponseCode::ServerFailure,
@@ -188,41 +696,41 @@ impl Into<ResponseCode> for u16 {
             21 => ResponseCode::BadAlgorithm,
             22 => ResponseCode::BadTruncation,
             23 => ResponseCode::BadCookie,
-            x => panic!("Unknown response code {x}"),
-        }
+            x => return Err(DecodeError::UnknownResponseCode(x)),
+        })
     }
 }
 
-impl Into<u8> for ResponseCode {
-    fn into(self) -> u8 {
-        match self {
-            Self::NoError => 0,
-            Self::FormatError => 1,
-            Self::ServerFailure => 2,
-            Self::NonExistentDomain => 3,
-            Self::NotImplemented => 4,
-            Self::Refused => 5,
-            Self::NameExists => 6,
-            Self::ResourceRecordSet => 7,
-            Self::ResourceRecordNotSet => 8,
-            Self::NotAuthorized => 9,
-            Self::NotInZone => 10,
-            Self::DSOTypeNotImplemented => 11,
-            Self::BadVersion => 16,
-            Self::BadKey => 17,
-            Self::BadTime => 18,
-            Self::BadMode => 19,
-            Self::BadName => 20,
-            Self::BadAlgorithm => 21,
-            Self::BadTruncation => 22,
-            Self::BadCookie => 23,
+impl From<ResponseCode> for u8 {
+    fn from(code: ResponseCode) -> Self {
+        match code {
+            ResponseCode::NoError => 0,
+            ResponseCode::FormatError => 1,
+            ResponseCode::ServerFailure => 2,
+            ResponseCode::NonExistentDomain => 3,
+            ResponseCode::NotImplemented => 4,
+            ResponseCode::Refused => 5,
+            ResponseCode::NameExists => 6,
+            ResponseCode::ResourceRecordSet => 7,
+            ResponseCode::ResourceRecordNotSet => 8,
+            ResponseCode::NotAuthorized => 9,
+            ResponseCode::NotInZone => 10,
+            ResponseCode::DSOTypeNotImplemented => 11,
+            ResponseCode::BadVersion => 16,
+            ResponseCode::BadKey => 17,
+            ResponseCode::BadTime => 18,
+            ResponseCode::BadMode => 19,
+            ResponseCode::BadName => 20,
+            ResponseCode::BadAlgorithm => 21,
+            ResponseCode::BadTruncation => 22,
+            ResponseCode::BadCookie => 23,
         }
     }
 }
 
-impl Into<u16> for ResponseCode {
-    fn into(self) -> u16 {
-        Into::<u8>::into(self) as u16
+impl From<ResponseCode> for u16 {
+    fn from(code: ResponseCode) -> Self {
+        u8::from(code) as u16
     }
 }
 
@@ -233,49 +741,54 @@ pub enum Operation {
     Status,
     Notify,
     Update,
+    #[allow(clippy::enum_variant_names)]
     StatefulOperation,
 }
 
-impl Into<Operation> for u8 {
-    fn into(self) -> Operation {
-        (self as u16).into()
+impl TryFrom<u8> for Operation {
+    type Error = DecodeError;
+
+    fn try_from(x: u8) -> Result<Self, Self::Error> {
+        (x as u16).try_into()
     }
 }
 
-impl Into<Operation> for u16 {
-    fn into(self) -> Operation {
-        match self {
+impl TryFrom<u16> for Operation {
+    type Error = DecodeError;
+
+    fn try_from(x: u16) -> Result<Self, Self::Error> {
+        Ok(match x {
             0 => Operation::Query,
             1 => Operation::InverseQuery,
             2 => Operation::Status,
             4 => Operation::Notify,
             5 => Operation::Update,
             6 => Operation::StatefulOperation,
-            x => panic!("Unknown operation with code {x}"),
-        }
+            x => return Err(DecodeError::UnknownOperation(x)),
+        })
     }
 }
 
-impl Into<u8> for Operation {
-    fn into(self) -> u8 {
-        match self {
-            Self::Query => 0,
-            Self::InverseQuery => 1,
-            Self::Status => 2,
-            Self::Notify => 4,
-            Self::Update => 5,
-            Self::StatefulOperation => 6,
+impl From<Operation> for u8 {
+    fn from(operation: Operation) -> Self {
+        match operation {
+            Operation::Query => 0,
+            Operation::InverseQuery => 1,
+            Operation::Status => 2,
+            Operation::Notify => 4,
+            Operation::Update => 5,
+            Operation::StatefulOperation => 6,
         }
     }
 }
 
-impl Into<u16> for Operation {
-    fn into(self) -> u16 {
-        Into::<u8>::into(self) as u16
+impl From<Operation> for u16 {
+    fn from(operation: Operation) -> Self {
+        u8::from(operation) as u16
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Class {
     Internet,
     Chaos,
@@ -285,9 +798,9 @@ pub enum Class {
     Unknown(u16),
 }
 
-impl Into<Class> for u16 {
-    fn into(self) -> Class {
-        match self {
+impl From<u16> for Class {
+    fn from(x: u16) -> Self {
+        match x {
             1 => Class::Internet,
             3 => Class::Chaos,
             4 => Class::Hesiod,
@@ -298,15 +811,15 @@ impl Into<Class> for u16 {
     }
 }
 
-impl Into<u16> for Class {
-    fn into(self) -> u16 {
-        match self {
-            Self::Internet => 1,
-            Self::Chaos => 3,
-            Self::Hesiod => 4,
-            Self::None => 254,
-            Self::Any => 255,
-            Self::Unknown(x) => x,
+impl From<Class> for u16 {
+    fn from(class: Class) -> Self {
+        match class {
+            Class::Internet => 1,
+            Class::Chaos => 3,
+            Class::Hesiod => 4,
+            Class::None => 254,
+            Class::Any => 255,
+            Class::Unknown(x) => x,
         }
     }
 }
@@ -319,6 +832,12 @@ pub struct Flags {
     is_truncated: bool,
     is_recursion_desired: bool,
     is_recursion_available: bool,
+    /// The reserved Z bit, kept only so a message re-encodes as it came.
+    is_reserved: bool,
+    /// The AD bit of RFC 4035 section 3.2.3.
+    is_authentic_data: bool,
+    /// The CD bit of RFC 4035 section 3.2.2.
+    is_checking_disabled: bool,
     operation: Operation,
     response_code: ResponseCode,
 }
@@ -339,22 +858,65 @@ impl Flags {
             is_truncated,
             is_recursion_desired,
             is_recursion_available,
+            is_reserved: false,
+            is_authentic_data: false,
+            is_checking_disabled: false,
             operation,
             response_code,
         }
     }
 
-    pub fn read_from_stream(stream: &mut dyn IStream) -> Self {
-        let flags = stream.read_u16();
-        Flags {
+    /// Whether the message is a query rather than a response.
+    pub fn is_query(&self) -> bool {
+        self.is_query
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.is_truncated
+    }
+
+    pub fn is_recursion_desired(&self) -> bool {
+        self.is_recursion_desired
+    }
+
+    pub fn is_authentic_data(&self) -> bool {
+        self.is_authentic_data
+    }
+
+    pub fn set_authentic_data(&mut self, is_authentic_data: bool) {
+        self.is_authentic_data = is_authentic_data;
+    }
+
+    pub fn is_checking_disabled(&self) -> bool {
+        self.is_checking_disabled
+    }
+
+    pub fn set_checking_disabled(&mut self, is_checking_disabled: bool) {
+        self.is_checking_disabled = is_checking_disabled;
+    }
+
+    pub fn response_code(&self) -> ResponseCode {
+        self.response_code.clone()
+    }
+
+    pub fn operation(&self) -> Operation {
+        self.operation.clone()
+    }
+
+    pub fn read_from_stream(stream: &mut dyn IStream) -> Result<Self, DecodeError> {
+        let flags = stream.read_u16()?;
+        Ok(Flags {
             is_query: flags & 0x8000 == 0,
             is_authoritative_answer: flags & 0x0400 != 0,
             is_truncated: flags & 0x0200 != 0,
             is_recursion_desired: flags & 0x0100 != 0,
             is_recursion_available: flags & 0x0080 != 0,
-            operation: ((flags & 0x7800) >> 11).into(),
-            response_code: (flags & 0x000f).into(),
-        }
+            is_reserved: flags & 0x0040 != 0,
+            is_authentic_data: flags & 0x0020 != 0,
+            is_checking_disabled: flags & 0x0010 != 0,
+            operation: ((flags & 0x7800) >> 11).try_into()?,
+            response_code: (flags & 0x000f).try_into()?,
+        })
     }
 
     pub fn write_to_stream(&self, stream: &mut dyn OStream) {
@@ -365,13 +927,16 @@ impl Flags {
         flags |= (self.is_truncated as u16) << 9;
         flags |= (self.is_recursion_desired as u16) << 8;
         flags |= (self.is_recursion_available as u16) << 7;
-        flags |= Into::<u16>::into(self.response_code.clone());
+        flags |= (self.is_reserved as u16) << 6;
+        flags |= (self.is_authentic_data as u16) << 5;
+        flags |= (self.is_checking_disabled as u16) << 4;
+        flags |= Into::<u16>::into(self.response_code.clone()) & 0x000f;
         stream.write_u16(flags);
     }
 }
 
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Question {
     pub name: Domain,
     pub typ: ResourceRecordType,
@@ -379,16 +944,16 @@ pub struct Question {
 }
 
 impl Question {
-    pub fn read_from_stream(stream: &mut dyn IStream) -> Self {
-        Question {
-            name: Domain::read_from_stream(stream),
-            typ: stream.read_u16().into(),
-            class: stream.read_u16().into(),
-        }
+    pub fn read_from_stream(stream: &mut dyn IStream) -> Result<Self, DecodeError> {
+        Ok(Question {
+            name: Domain::read_from_stream(stream)?,
+            typ: stream.read_u16()?.into(),
+            class: stream.read_u16()?.into(),
+        })
     }
 
-    pub fn write_to_stream(&self, stream: &mut dyn OStream) {
-        self.name.write_to_stream(stream);
+    pub fn write_to_stream(&self, stream: &mut dyn OStream, names: &mut Names) {
+        self.name.write_to_stream(stream, names);
         stream.write_u16(self.typ.clone().into());
         stream.write_u16(self.class.clone().into());
     }
@@ -397,10 +962,10 @@ impl Question {
 
 #[derive(Clone, Debug)]
 pub struct ResourceRecord {
-    name: Domain,
-    class: Class,
-    time_to_live: u32,
-    data: ResourceRecordData,
+    pub name: Domain,
+    pub class: Class,
+    pub time_to_live: u32,
+    pub data: ResourceRecordData,
 }
 
 impl ResourceRecord {
@@ -412,23 +977,102 @@ impl ResourceRecord {
         }
     }
 
-    pub fn read_from_stream(stream: &mut dyn IStream) -> Self {
-        let name = Domain::read_from_stream(stream);
-        let typ: ResourceRecordType = stream.read_u16().into();
-        ResourceRecord {
+    pub fn read_from_stream(stream: &mut dyn IStream) -> Result<Self, DecodeError> {
+        let name = Domain::read_from_stream(stream)?;
+        let typ: ResourceRecordType = stream.read_u16()?.into();
+        Ok(ResourceRecord {
             name,
-            class: stream.read_u16().into(),
-            time_to_live: stream.read_u32(),
-            data: ResourceRecordData::read_from_stream(stream, &typ),
-        }
+            class: stream.read_u16()?.into(),
+            time_to_live: stream.read_u32()?,
+            data: ResourceRecordData::read_from_stream(stream, &typ)?,
+        })
     }
 
-    pub fn write_to_stream(&self, stream: &mut dyn OStream) {
-        self.name.write_to_stream(stream);
+    pub fn write_to_stream(&self, stream: &mut dyn OStream, names: &mut Names) {
+        self.name.write_to_stream(stream, names);
         stream.write_u16(Into::<ResourceRecordType>::into(self.data.clone()).into());
         stream.write_u16(self.class.clone().into());
         stream.write_u32(self.time_to_live);
-        self.data.write_to_stream(stream);
+        self.data.write_to_stream(stream, names);
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+
+/// The contents of an EDNS(0) OPT pseudo-record, as described in RFC 6891.
+#[derive(Clone, Debug)]
+pub struct Edns {
+    /// The largest UDP payload the sender can reassemble.
+    pub udp_size: u16,
+    /// The upper eight bits of the response code, as decoded. When encoding
+    /// they are taken from the message's response code instead.
+    pub extended_rcode: u8,
+    pub version: u8,
+    pub dnssec_ok: bool,
+    pub options: Vec<EdnsOption>,
+}
+
+impl Edns {
+    pub fn new(udp_size: u16, dnssec_ok: bool) -> Self {
+        Self {
+            udp_size,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok,
+            options: vec![],
+        }
+    }
+
+    fn from_record(record: ResourceRecord) -> Result<Self, DecodeError> {
+        let ResourceRecordData::Options(data) = record.data else {
+            return Err(DecodeError::BadOptRecord);
+        };
+        if !record.name.0.is_empty() {
+            return Err(DecodeError::BadOptRecord);
+        }
+        let mut options = vec![];
+        let mut rest = &data[..];
+        while !rest.is_empty() {
+            if rest.len() < 4 {
+                return Err(DecodeError::BadOptRecord);
+            }
+            let code = u16::from_be_bytes([rest[0], rest[1]]);
+            let length = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+            if rest.len() < 4 + length {
+                return Err(DecodeError::BadOptRecord);
+            }
+            options.push(EdnsOption { code, data: rest[4..4 + length].to_vec() });
+            rest = &rest[4 + length..];
+        }
+        Ok(Self {
+            udp_size: record.class.into(),
+            extended_rcode: (record.time_to_live >> 24) as u8,
+            version: (record.time_to_live >> 16) as u8,
+            dnssec_ok: record.time_to_live & 0x8000 != 0,
+            options,
+        })
+    }
+
+    fn to_record(&self, response_code: ResponseCode) -> ResourceRecord {
+        let extended_rcode = (u16::from(response_code) >> 4) as u32;
+        let mut data = vec![];
+        for option in self.options.iter() {
+            data.extend(option.code.to_be_bytes());
+            data.extend((option.data.len() as u16).to_be_bytes());
+            data.extend(option.data.iter());
+        }
+        ResourceRecord::new(
+            Domain(vec![]),
+            self.udp_size.into(),
+            extended_rcode << 24 | (self.version as u32) << 16 | (self.dnssec_ok as u32) << 15,
+            ResourceRecordData::Options(data),
+        )
     }
 }
 
@@ -441,6 +1085,7 @@ pub struct Message {
     pub answers: Vec<ResourceRecord>,
     pub authoritative_records: Vec<ResourceRecord>,
     pub additional_records: Vec<ResourceRecord>,
+    pub edns: Option<Edns>,
 }
 
 impl Message {
@@ -455,36 +1100,71 @@ impl Message {
             questions, answers,
             authoritative_records,
             additional_records,
+            edns: None,
         }
     }
 
-    pub fn read_from_stream(stream: &mut dyn IStream) -> Self {
-        let id = stream.read_u16();
-        let flags = Flags::read_from_stream(stream);
-        let questions_count = stream.read_u16();
-        let answers_count = stream.read_u16();
-        let authoritative_records_count = stream.read_u16();
-        let additional_records_count = stream.read_u16();
-        Self {
-            id, flags,
-            questions: (0..questions_count).map(|_| Question::read_from_stream(stream)).collect(),
-            answers: (0..answers_count).map(|_| ResourceRecord::read_from_stream(stream)).collect(),
-            authoritative_records: (0..authoritative_records_count).map(|_| ResourceRecord::read_from_stream(stream)).collect(),
-            additional_records: (0..additional_records_count).map(|_| ResourceRecord::read_from_stream(stream)).collect(),
+    pub fn read_from_stream(stream: &mut dyn IStream) -> Result<Self, DecodeError> {
+        let id = stream.read_u16()?;
+        let mut flags = Flags::read_from_stream(stream)?;
+        let questions_count = stream.read_u16()?;
+        let answers_count = stream.read_u16()?;
+        let authoritative_records_count = stream.read_u16()?;
+        let additional_records_count = stream.read_u16()?;
+        let questions = (0..questions_count).map(|_| Question::read_from_stream(stream)).collect::<Result<_, _>>()?;
+        let answers = (0..answers_count).map(|_| ResourceRecord::read_from_stream(stream)).collect::<Result<_, _>>()?;
+        let authoritative_records = (0..authoritative_records_count).map(|_| ResourceRecord::read_from_stream(stream)).collect::<Result<_, _>>()?;
+
+        let mut additional_records = vec![];
+        let mut edns = None;
+        for _ in 0..additional_records_count {
+            let record = ResourceRecord::read_from_stream(stream)?;
+            if ResourceRecordType::from(&record.data) != ResourceRecordType::Options {
+                additional_records.push(record);
+            } else if edns.is_none() {
+                edns = Some(Edns::from_record(record)?);
+            } else {
+                return Err(DecodeError::BadOptRecord);
+            }
         }
+        if let Some(edns) = &edns {
+            let response_code = (edns.extended_rcode as u16) << 4 | u16::from(flags.response_code.clone());
+            flags.response_code = response_code.try_into()?;
+        }
+
+        Ok(Self {
+            id, flags,
+            questions, answers,
+            authoritative_records,
+            additional_records,
+            edns,
+        })
     }
 
     pub fn write_to_stream(&self, stream: &mut dyn OStream) {
+        self.write_sections(stream);
+    }
+
+    /// Writes the message, returning where the questions end followed by
+    /// where each answer, authority and additional record ends.
+    fn write_sections(&self, stream: &mut dyn OStream) -> Vec<usize> {
         stream.write_u16(self.id);
         self.flags.write_to_stream(stream);
         stream.write_u16(self.questions.len() as u16);
         stream.write_u16(self.answers.len() as u16);
         stream.write_u16(self.authoritative_records.len() as u16);
-        stream.write_u16(self.additional_records.len() as u16);
-        self.questions.iter().for_each(|q| q.write_to_stream(stream));
-        self.answers.iter().for_each(|a| a.write_to_stream(stream));
-        self.authoritative_records.iter().for_each(|ar| ar.write_to_stream(stream));
-        self.additional_records.iter().for_each(|ar| ar.write_to_stream(stream));
+        stream.write_u16((self.additional_records.len() + self.edns.is_some() as usize) as u16);
+        let mut names = Names::default();
+        self.questions.iter().for_each(|q| q.write_to_stream(stream, &mut names));
+        let mut ends = vec![stream.position()];
+        for record in self.answers.iter().chain(&self.authoritative_records).chain(&self.additional_records) {
+            record.write_to_stream(stream, &mut names);
+            ends.push(stream.position());
+        }
+        if let Some(edns) = &self.edns {
+            edns.to_record(self.flags.response_code.clone()).write_to_stream(stream, &mut names);
+        }
+        ends
     }
 
     pub fn with_id(self, id: u16) -> Self {
@@ -493,6 +1173,51 @@ impl Message {
             questions: self.questions, answers: self.answers,
             authoritative_records: self.authoritative_records,
             additional_records: self.additional_records,
+            edns: self.edns,
+        }
+    }
+
+    /// The size of the message once encoded.
+    pub fn encoded_len(&self) -> usize {
+        let mut buffer = VecWriter::new();
+        self.write_to_stream(&mut buffer);
+        buffer.bytes().len()
+    }
+
+    /// Drops records from the end of the message until it fits in `max_size`
+    /// bytes, keeping RRsets whole. Additional records can go freely, but
+    /// once an answer or authority record is dropped the truncated flag is
+    /// set so the client knows to retry over TCP. Names are only compressed
+    /// against earlier ones, so the message is encoded once and the size of
+    /// every shorter version read off that.
+    pub fn truncate(&mut self, max_size: usize) {
+        let mut buffer = VecWriter::new();
+        let ends = self.write_sections(&mut buffer);
+        let size = buffer.bytes().len();
+        if size <= max_size {
+            return;
+        }
+        let records: Vec<&ResourceRecord> = self.answers.iter()
+            .chain(&self.authoritative_records)
+            .chain(&self.additional_records)
+            .collect();
+        let (answers, authority) = (self.answers.len(), self.authoritative_records.len());
+        let section = |i: usize| (i >= answers) as u8 + (i >= answers + authority) as u8;
+        let same_rrset = |i: usize| section(i - 1) == section(i)
+            && records[i - 1].name == records[i].name
+            && records[i - 1].class == records[i].class
+            && ResourceRecordType::from(&records[i - 1].data) == ResourceRecordType::from(&records[i].data);
+
+        let edns_size = size - ends[records.len()];
+        let mut kept = ends.iter().rposition(|&end| end + edns_size <= max_size).unwrap_or(0);
+        while kept > 0 && same_rrset(kept) {
+            kept -= 1;
+        }
+        if kept < answers + authority || ends[0] + edns_size > max_size {
+            self.flags.is_truncated = true;
         }
+        self.additional_records.truncate(kept.saturating_sub(answers + authority));
+        self.authoritative_records.truncate(kept.saturating_sub(answers).min(authority));
+        self.answers.truncate(kept.min(answers));
     }
 }
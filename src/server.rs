@@ -0,0 +1,353 @@
+use std::{
+    io,
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::{atomic::{AtomicUsize, Ordering}, Arc},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    cache::{Answer, HostCache, Statistics},
+    error::DecodeError,
+    message::*,
+    tcp::TCPStream,
+    udp::UDPStream,
+    upstream::UpstreamPool,
+};
+
+
+/// The largest response sent over UDP to a client that did not ask for more.
+const MAX_UDP_RESPONSE_SIZE: usize = 512;
+
+/// The largest message a TCP length prefix can frame.
+const MAX_TCP_MESSAGE_SIZE: usize = 65535;
+
+/// The UDP payload size we advertise with EDNS, and the most we will send.
+const EDNS_UDP_SIZE: u16 = 1232;
+
+/// The most CNAME links followed while answering one question.
+const MAX_CNAME_CHAIN: usize = 16;
+
+
+pub struct Server {
+    cache: HostCache,
+    upstream: UpstreamPool,
+    tcp_idle_timeout: Duration,
+    max_tcp_connections: usize,
+    /// The number of TCP connections being served.
+    tcp_connections: AtomicUsize,
+}
+
+impl Server {
+    pub fn new(cache: HostCache, upstream: UpstreamPool, tcp_idle_timeout: Duration, max_tcp_connections: usize) -> Self {
+        Self {
+            cache, upstream,
+            tcp_idle_timeout, max_tcp_connections,
+            tcp_connections: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn statistics(&self) -> Statistics {
+        self.cache.statistics()
+    }
+
+    /// Answers a query. Each question is answered on its own, from local data
+    /// and the cache or else by forwarding it upstream, and the answers are
+    /// merged into one response. The response is authoritative only if every
+    /// answer is, and its RCODE is SERVFAIL if any question failed, or else
+    /// the first other error among the answers. It carries an OPT record only
+    /// if the query did. Opcodes other than QUERY get NOTIMP, and responses
+    /// get no reply at all, as answering them would only invite a loop. The
+    /// CD bit is copied from the query, and the AD bit is passed on only if
+    /// every answer came straight from an upstream that set it, and the
+    /// query asked for it with AD or DO as in RFC 6840 section 5.8.
+    pub fn handle(&self, message: &Message) -> Option<Message> {
+        let dnssec_ok = message.edns.as_ref().is_some_and(|edns| edns.dnssec_ok);
+        let edns = message.edns.as_ref().map(|_| Edns::new(EDNS_UDP_SIZE, dnssec_ok));
+        let failure = |response_code| {
+            let mut response = Message::new(
+                message.id,
+                Flags::new(
+                    false, false, false, message.flags.is_recursion_desired(), true,
+                    message.flags.operation(), response_code
+                ),
+                message.questions.clone(), vec![], vec![], vec![]);
+            response.flags.set_checking_disabled(message.flags.is_checking_disabled());
+            response.edns = edns.clone();
+            response
+        };
+        if !message.flags.is_query() {
+            return None;
+        }
+        if !matches!(message.flags.operation(), Operation::Query) {
+            return Some(failure(ResponseCode::NotImplemented));
+        }
+        if message.edns.as_ref().is_some_and(|edns| edns.version > 0) {
+            return Some(failure(ResponseCode::BadVersion));
+        }
+        if message.questions.is_empty() {
+            return Some(failure(ResponseCode::FormatError));
+        }
+
+        let mut is_truncated = false;
+        let mut is_authentic_data = dnssec_ok || message.flags.is_authentic_data();
+        let answers: Vec<Answer> = message.questions.iter()
+            .map(|question| match self.answer_question(question, message) {
+                Some(answer) => {
+                    is_authentic_data = false;
+                    answer
+                },
+                None => {
+                    let response = self.forward_question(question, message);
+                    is_truncated |= response.flags.is_truncated();
+                    is_authentic_data &= response.flags.is_authentic_data();
+                    upstream_answer(response)
+                },
+            })
+            .collect();
+        let is_authoritative = answers.iter().all(|answer| answer.is_authoritative);
+        let response_code = if answers.iter().any(|answer| matches!(answer.response_code, ResponseCode::ServerFailure)) {
+            ResponseCode::ServerFailure
+        } else {
+            answers.iter()
+                .map(|answer| answer.response_code.clone())
+                .find(|code| !matches!(code, ResponseCode::NoError))
+                .unwrap_or(ResponseCode::NoError)
+        };
+        let (records, authority, additional) = answers.into_iter()
+            .fold((vec![], vec![], vec![]), |(mut records, mut authority, mut additional), answer| {
+                records.extend(answer.records);
+                authority.extend(answer.authority);
+                additional.extend(answer.additional);
+                (records, authority, additional)
+            });
+        let mut response = Message::new(
+            message.id,
+            Flags::new(
+                false, is_authoritative, is_truncated, message.flags.is_recursion_desired(), true,
+                message.flags.operation(), response_code
+            ),
+            message.questions.clone(), records, authority, additional);
+        response.flags.set_authentic_data(is_authentic_data);
+        response.flags.set_checking_disabled(message.flags.is_checking_disabled());
+        response.edns = edns;
+        Some(response)
+    }
+
+    /// Answers `question` from the cache, following any CNAMEs in the answer
+    /// as described in RFC 1034 section 3.6.2. A target the cache knows
+    /// nothing about is looked up upstream, which follows the rest of the
+    /// chain itself. Returns `None` if the cache has nothing for `question`.
+    /// CHAOS questions are answered here, and other classes are refused.
+    fn answer_question(&self, question: &Question, message: &Message) -> Option<Answer> {
+        match question.class {
+            Class::Internet | Class::Any => {},
+            Class::Chaos => return Some(chaos_answer(question)),
+            _ => return Some(refused()),
+        }
+        let mut answer = self.cache.handle_question(question)?;
+        if matches!(question.typ, ResourceRecordType::CNAME | ResourceRecordType::Any) {
+            return Some(answer);
+        }
+        let mut chain = vec![question.name.clone()];
+        loop {
+            let name = chain.last().unwrap();
+            let Some(target) = answer.records.iter().find_map(|record| match &record.data {
+                ResourceRecordData::CNAME(target) if record.name == *name => Some(target.clone()),
+                _ => None,
+            }) else {
+                break;
+            };
+            if chain.contains(&target) || chain.len() > MAX_CNAME_CHAIN {
+                eprintln!("CNAME chain from {} loops or is too long", question.name);
+                answer.response_code = ResponseCode::ServerFailure;
+                break;
+            }
+            chain.push(target.clone());
+
+            let next = Question { name: target, ..question.clone() };
+            let (next, forwarded) = match self.cache.handle_question(&next) {
+                Some(next) => (next, false),
+                None => (upstream_answer(self.forward_question(&next, message)), true),
+            };
+            answer.records.extend(next.records);
+            answer.authority = next.authority;
+            answer.additional = next.additional;
+            answer.response_code = next.response_code;
+            if forwarded {
+                break;
+            }
+        }
+        Some(answer)
+    }
+
+    /// Forwards `question` upstream on its own, with the rest of the header
+    /// and our own OPT record, and caches the response.
+    fn forward_question(&self, question: &Question, message: &Message) -> Message {
+        let mut query = message.clone();
+        query.questions = vec![question.clone()];
+        let dnssec_ok = query.edns.as_ref().is_some_and(|edns| edns.dnssec_ok);
+        query.edns = Some(Edns::new(EDNS_UDP_SIZE, dnssec_ok));
+        let response = self.upstream.forward(&query);
+        self.cache.store(&response);
+        response
+    }
+
+    /// Answers UDP queries on `workers` threads that all receive from
+    /// `socket`, so a query waiting on a slow upstream only holds up its own
+    /// thread. Each thread replies to the address its query came from.
+    pub fn serve_udp(self: Arc<Self>, socket: UdpSocket, workers: usize) {
+        for _ in 1..workers {
+            let server = self.clone();
+            let socket = socket.try_clone().expect("Failed to clone UDP socket");
+            thread::spawn(move || server.serve_udp_worker(socket));
+        }
+        self.serve_udp_worker(socket);
+    }
+
+    fn serve_udp_worker(&self, socket: UdpSocket) {
+        let mut stream = UDPStream::new(socket);
+        loop {
+            let (message, source) = match stream.receive() {
+                Ok(received) => received,
+                Err(error) => {
+                    eprintln!("Failed to receive message: {error}");
+                    continue;
+                },
+            };
+            let response = match message {
+                Ok(message) => {
+                    let max_size = message.edns.as_ref().map_or(MAX_UDP_RESPONSE_SIZE, |edns| {
+                        (edns.udp_size as usize).clamp(MAX_UDP_RESPONSE_SIZE, EDNS_UDP_SIZE as usize)
+                    });
+                    self.handle(&message).map(|mut response| {
+                        response.truncate(max_size);
+                        response
+                    })
+                },
+                Err(error) => {
+                    eprintln!("Malformed message from {source}: {error}");
+                    decode_failure(stream.datagram(), &error)
+                },
+            };
+            if let Some(response) = response {
+                if let Err(error) = stream.send(&response, source) {
+                    eprintln!("Failed to send response to {source}: {error}");
+                }
+            }
+        }
+    }
+
+    /// Accepts TCP connections, serving each one on its own thread. Once
+    /// `max_tcp_connections` are being served, new connections are closed
+    /// straight away, so a flood of them cannot exhaust our threads.
+    pub fn serve_tcp(self: Arc<Self>, listener: TcpListener) {
+        for connection in listener.incoming() {
+            match connection {
+                Ok(connection) => {
+                    if self.tcp_connections.load(Ordering::Relaxed) >= self.max_tcp_connections {
+                        continue;
+                    }
+                    self.tcp_connections.fetch_add(1, Ordering::Relaxed);
+                    let server = self.clone();
+                    thread::spawn(move || {
+                        let peer = connection.peer_addr();
+                        if let Err(error) = server.serve_connection(connection) {
+                            eprintln!("TCP connection from {peer:?} failed: {error}");
+                        }
+                        server.tcp_connections.fetch_sub(1, Ordering::Relaxed);
+                    });
+                },
+                Err(error) => eprintln!("Failed to accept connection: {error}"),
+            }
+        }
+    }
+
+    /// Answers queries on one connection in the order they arrive, until the
+    /// peer closes it or it has been idle for too long.
+    fn serve_connection(&self, connection: TcpStream) -> io::Result<()> {
+        connection.set_read_timeout(Some(self.tcp_idle_timeout))?;
+        let mut stream = TCPStream::new(connection);
+        loop {
+            let message = match stream.receive() {
+                Ok(Some(message)) => message,
+                Ok(None) => return Ok(()),
+                Err(error) if matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => return Ok(()),
+                Err(error) => return Err(error),
+            };
+            let mut response = match message {
+                Ok(message) => match self.handle(&message) {
+                    Some(response) => response,
+                    None => continue,
+                },
+                Err(error) => match decode_failure(stream.message(), &error) {
+                    Some(response) => response,
+                    None => return Ok(()),
+                },
+            };
+            response.truncate(MAX_TCP_MESSAGE_SIZE);
+            stream.send(&response)?;
+        }
+    }
+}
+
+
+fn upstream_answer(response: Message) -> Answer {
+    Answer {
+        response_code: response.flags.response_code(),
+        records: response.answers,
+        authority: response.authoritative_records,
+        additional: response.additional_records,
+        is_authoritative: false,
+    }
+}
+
+fn refused() -> Answer {
+    Answer {
+        response_code: ResponseCode::Refused,
+        records: vec![],
+        authority: vec![],
+        additional: vec![],
+        is_authoritative: false,
+    }
+}
+
+/// Answers `version.bind` and `version.server` in the CHAOS class with our
+/// version, and refuses any other CHAOS question.
+fn chaos_answer(question: &Question) -> Answer {
+    if !["version.bind", "version.server"].iter().any(|name| question.name == name.parse().unwrap()) {
+        return refused();
+    }
+    let version = format!("miadon {}", env!("CARGO_PKG_VERSION"));
+    let records = match question.typ {
+        ResourceRecordType::TXT | ResourceRecordType::Any => vec![
+            ResourceRecord::new(question.name.clone(), Class::Chaos, 0, ResourceRecordData::TXT(vec![version.into_bytes()])),
+        ],
+        _ => vec![],
+    };
+    Answer { response_code: ResponseCode::NoError, records, authority: vec![], additional: vec![], is_authoritative: true }
+}
+
+
+/// Builds the response to a message that could not be decoded, echoing as much
+/// of the header as is available. Returns `None` when the message is too
+/// short to carry a header or is itself a response, as replying to either
+/// would only invite a loop.
+fn decode_failure(message: &[u8], error: &DecodeError) -> Option<Message> {
+    if message.len() < 12 || message[2] & 0x80 != 0 {
+        return None;
+    }
+    let id = u16::from_be_bytes([message[0], message[1]]);
+    let is_recursion_desired = message[2] & 0x01 != 0;
+    let response_code = match error {
+        DecodeError::UnknownOperation(_) => ResponseCode::NotImplemented,
+        _ => ResponseCode::FormatError,
+    };
+    Some(Message::new(
+        id,
+        Flags::new(
+            false, false, false, is_recursion_desired, true,
+            Operation::Query, response_code
+        ),
+        vec![], vec![], vec![], vec![]))
+}
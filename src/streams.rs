@@ -1,24 +1,34 @@
+use crate::error::DecodeError;
+
+
 pub trait IStream {
-    fn read_u8(&mut self) -> u8;
+    fn read_u8(&mut self) -> Result<u8, DecodeError>;
+
+    /// The offset of the next byte to be read, from the start of the message.
+    fn position(&self) -> usize;
 
-    fn read_u16(&mut self) -> u16 {
-        let high = self.read_u8() as u16;
-        let low = self.read_u8() as u16;
-        (high << 8) | low
+    /// Moves to an offset from the start of the message, as needed to follow
+    /// compression pointers.
+    fn seek(&mut self, position: usize) -> Result<(), DecodeError>;
+
+    fn read_u16(&mut self) -> Result<u16, DecodeError> {
+        let high = self.read_u8()? as u16;
+        let low = self.read_u8()? as u16;
+        Ok((high << 8) | low)
     }
 
-    fn read_u32(&mut self) -> u32 {
-        let high = self.read_u16() as u32;
-        let low  = self.read_u16() as u32;
-        (high << 16) | low
+    fn read_u32(&mut self) -> Result<u32, DecodeError> {
+        let high = self.read_u16()? as u32;
+        let low  = self.read_u16()? as u32;
+        Ok((high << 16) | low)
     }
 
-    fn read_bytes(&mut self, len: usize) -> Vec<u8> {
-        (0..len).map(|_| self.read_u8()).collect::<Vec<_>>()
+    fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>, DecodeError> {
+        (0..len).map(|_| self.read_u8()).collect()
     }
 
-    fn read_string(&mut self, len: usize) -> String {
-        String::from_utf8(self.read_bytes(len)).unwrap()
+    fn read_string(&mut self, len: usize) -> Result<String, DecodeError> {
+        String::from_utf8(self.read_bytes(len)?).map_err(|_| DecodeError::InvalidUtf8)
     }
 }
 
@@ -26,6 +36,10 @@ pub trait IStream {
 pub trait OStream {
     fn write_u8(&mut self, x: u8);
 
+    /// The offset of the next byte to be written, from the start of the
+    /// message, as needed to emit compression pointers.
+    fn position(&self) -> usize;
+
     fn write_u16(&mut self, x: u16) {
         self.write_u8((x >> 8) as u8);
         self.write_u8((x & 0xff) as u8);
@@ -36,11 +50,96 @@ pub trait OStream {
         self.write_u16((x & 0xffff) as u16);
     }
 
-    fn write_bytes(&mut self, bytes: Vec<u8>) {
-        bytes.iter().for_each(|c| self.write_u8(c.clone()));
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        bytes.iter().for_each(|c| self.write_u8(*c));
+    }
+
+    fn write_string(&mut self, string: &str) {
+        self.write_bytes(string.as_bytes());
+    }
+}
+
+
+/// Reads a message held in memory, such as a received datagram or a
+/// captured packet.
+pub struct SliceReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> SliceReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    /// Reads the next `len` bytes in place, borrowing them from the message
+    /// rather than copying them out.
+    pub fn read_slice(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let bytes = self.bytes.get(self.position..self.position.saturating_add(len)).ok_or(DecodeError::Truncated)?;
+        self.position += len;
+        Ok(bytes)
+    }
+}
+
+impl IStream for SliceReader<'_> {
+    fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        let byte = *self.bytes.get(self.position).ok_or(DecodeError::Truncated)?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn position(&self) -> usize {
+        self.position
+    }
+
+    fn seek(&mut self, position: usize) -> Result<(), DecodeError> {
+        if position > self.bytes.len() {
+            return Err(DecodeError::Truncated);
+        }
+        self.position = position;
+        Ok(())
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>, DecodeError> {
+        self.read_slice(len).map(<[u8]>::to_vec)
+    }
+}
+
+
+/// Collects an encoded message in memory. Positions are counted from `start`,
+/// so that part of a message can be encoded on its own, as RDATA is to learn
+/// its length before it is written.
+#[derive(Default)]
+pub struct VecWriter {
+    start: usize,
+    bytes: Vec<u8>,
+}
+
+impl VecWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A writer for the part of a message that begins at offset `start`.
+    pub fn starting_at(start: usize) -> Self {
+        Self { start, bytes: vec![] }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl OStream for VecWriter {
+    fn write_u8(&mut self, x: u8) {
+        self.bytes.push(x);
+    }
+
+    fn position(&self) -> usize {
+        self.start + self.bytes.len()
     }
 
-    fn write_string(&mut self, string: String) {
-        string.chars().for_each(|c| self.write_u8(c as u8));
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
     }
 }
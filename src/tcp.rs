@@ -0,0 +1,55 @@
+use std::{io::{self, Read, Write}, net::TcpStream};
+
+use crate::{error::DecodeError, message::Message, streams::{SliceReader, VecWriter}};
+
+
+/// A TCP connection carrying messages framed by a two byte length prefix, as
+/// described in RFC 1035 section 4.2.2.
+pub struct TCPStream {
+    stream: TcpStream,
+
+    in_buffer: Vec<u8>,
+}
+
+impl TCPStream {
+    pub fn new(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            in_buffer: vec![],
+        }
+    }
+
+    /// Waits for the next message on the connection and decodes it. Returns
+    /// the message or why it could not be decoded, or `None` once the peer
+    /// has closed the connection between messages.
+    pub fn receive(&mut self) -> io::Result<Option<Result<Message, DecodeError>>> {
+        let mut length = [0u8; 2];
+        match self.stream.read_exact(&mut length) {
+            Ok(()) => {},
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(error) => return Err(error),
+        }
+        self.in_buffer.resize(u16::from_be_bytes(length) as usize, 0);
+        self.stream.read_exact(&mut self.in_buffer)?;
+        Ok(Some(Message::read_from_stream(&mut SliceReader::new(self.message()))))
+    }
+
+    /// The message most recently returned by `receive`.
+    pub fn message(&self) -> &[u8] {
+        &self.in_buffer
+    }
+
+    /// Sends `message` prefixed with its length. A message too long for the
+    /// prefix is not sent, and is an error, rather than sent with a length
+    /// that would desync the connection.
+    pub fn send(&mut self, message: &Message) -> io::Result<()> {
+        let mut buffer = VecWriter::new();
+        message.write_to_stream(&mut buffer);
+        let Ok(length) = u16::try_from(buffer.bytes().len()) else {
+            let length = buffer.bytes().len();
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("message of {length} bytes is too long for TCP")));
+        };
+        let frame = [&length.to_be_bytes()[..], buffer.bytes()].concat();
+        self.stream.write_all(&frame)
+    }
+}
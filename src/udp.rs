@@ -1,57 +1,55 @@
-use std::net::{UdpSocket, SocketAddr};
+use std::{io, net::{UdpSocket, SocketAddr}};
 
-use crate::streams::{IStream, OStream};
+use crate::{error::DecodeError, message::Message, streams::{SliceReader, VecWriter}};
 
 
+/// The largest payload a UDP datagram can carry.
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+
+/// A UDP socket carrying one message per datagram. Every datagram is read on
+/// its own and every response is sent to an explicit address, so nothing
+/// is carried over from one exchange to the next.
 pub struct UDPStream {
     socket: UdpSocket,
-    target: Option<SocketAddr>,
 
-    in_buffer: [u8; 1024],
+    in_buffer: Vec<u8>,
     in_buffer_count: usize,
-    in_buffer_index: usize,
-    
-    out_buffer: Vec<u8>,
 }
 
 impl UDPStream {
     pub fn new(socket: UdpSocket) -> Self {
         Self {
-            socket, target: None,
-            in_buffer: [0u8; 1024],
-            in_buffer_count: 0, in_buffer_index: 1024,
-            out_buffer: vec![],
+            socket,
+            in_buffer: vec![0u8; MAX_DATAGRAM_SIZE],
+            in_buffer_count: 0,
         }
     }
 
-    pub fn set_target(&mut self, target: SocketAddr) {
-        self.target = Some(target);
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
     }
 
-    pub fn flush(&mut self) {
-        match self.target {
-            Some(target) => self.socket.send_to(&self.out_buffer, target).unwrap(),
-            None => panic!("Tried to send data with no target"),
-        };
-        self.out_buffer.clear();
+    /// Waits for the next datagram and decodes the message in it. Returns
+    /// the message, or why it could not be decoded, along with the address
+    /// it came from.
+    pub fn receive(&mut self) -> io::Result<(Result<Message, DecodeError>, SocketAddr)> {
+        self.in_buffer_count = 0;
+        let (buffer_count, source) = self.socket.recv_from(&mut self.in_buffer)?;
+        self.in_buffer_count = buffer_count;
+        Ok((Message::read_from_stream(&mut SliceReader::new(self.datagram())), source))
     }
-}
 
-impl IStream for UDPStream {
-    fn read_u8(&mut self) -> u8 {
-        if self.in_buffer_count == 0 || self.in_buffer_count == self.in_buffer_index {
-            let (buffer_count, target) = self.socket.recv_from(&mut self.in_buffer).expect("Failed to read from stream");
-            self.in_buffer_index = 0;
-            self.in_buffer_count = buffer_count;
-            self.target = Some(target);
-        }
-        self.in_buffer_index += 1;
-        self.in_buffer[self.in_buffer_index - 1]
+    /// The datagram most recently returned by `receive`.
+    pub fn datagram(&self) -> &[u8] {
+        &self.in_buffer[..self.in_buffer_count]
     }
-}
 
-impl OStream for UDPStream {
-    fn write_u8(&mut self, x: u8) {
-        self.out_buffer.push(x);
+    /// Sends `message` in a single datagram to `target`.
+    pub fn send(&self, message: &Message, target: SocketAddr) -> io::Result<()> {
+        let mut buffer = VecWriter::new();
+        message.write_to_stream(&mut buffer);
+        self.socket.send_to(buffer.bytes(), target)?;
+        Ok(())
     }
 }
@@ -0,0 +1,252 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io,
+    net::{SocketAddr, TcpStream, UdpSocket},
+    str::FromStr,
+    sync::{atomic::{AtomicUsize, Ordering}, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{message::*, tcp::TCPStream, udp::UDPStream};
+
+
+/// Returns a fresh random number, good enough for query IDs.
+pub fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+
+pub struct Upstream {
+    address: SocketAddr,
+    timeout: Duration,
+    retries: usize,
+}
+
+impl Upstream {
+    pub fn new(address: SocketAddr, timeout: Duration, retries: usize) -> Self {
+        Self { address, timeout, retries }
+    }
+
+    /// Sends `query` to the upstream resolver and waits for the matching
+    /// reply, retrying over TCP if the reply was truncated. If that fails the
+    /// truncated reply is returned, so the client can retry for itself.
+    /// Returns `None` if nothing arrives within the allowed attempts.
+    pub fn exchange(&self, query: &Message) -> io::Result<Option<Message>> {
+        match self.exchange_udp(query)? {
+            Some(response) if response.flags.is_truncated() => match self.exchange_tcp(query) {
+                Ok(Some(full_response)) => Ok(Some(full_response)),
+                Ok(None) => Ok(Some(response)),
+                Err(error) => {
+                    eprintln!("Failed to query upstream {} over TCP: {error}", self.address);
+                    Ok(Some(response))
+                },
+            },
+            response => Ok(response),
+        }
+    }
+
+    fn exchange_tcp(&self, query: &Message) -> io::Result<Option<Message>> {
+        let connection = TcpStream::connect_timeout(&self.address, self.timeout)?;
+        connection.set_read_timeout(Some(self.timeout))?;
+        connection.set_write_timeout(Some(self.timeout))?;
+        let mut stream = TCPStream::new(connection);
+
+        let id = random_u64() as u16;
+        stream.send(&query.clone().with_id(id))?;
+        let Some(response) = stream.receive()? else {
+            return Ok(None);
+        };
+        match response {
+            Ok(response) if response.id == id && response.questions == query.questions => Ok(Some(response)),
+            Ok(_) => Ok(None),
+            Err(error) => {
+                eprintln!("Malformed reply from upstream {} over TCP: {error}", self.address);
+                Ok(None)
+            },
+        }
+    }
+
+    fn exchange_udp(&self, query: &Message) -> io::Result<Option<Message>> {
+        let bind_address = if self.address.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let mut stream = UDPStream::new(UdpSocket::bind(bind_address)?);
+
+        for _ in 0..=self.retries {
+            let id = random_u64() as u16;
+            stream.send(&query.clone().with_id(id), self.address)?;
+
+            let deadline = Instant::now() + self.timeout;
+            while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+                if remaining.is_zero() {
+                    break;
+                }
+                stream.socket().set_read_timeout(Some(remaining))?;
+                let (response, source) = match stream.receive() {
+                    Ok(received) => received,
+                    Err(error) if matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => break,
+                    Err(error) => return Err(error),
+                };
+                if source != self.address {
+                    continue;
+                }
+                match response {
+                    Ok(response) if response.id == id && response.questions == query.questions => return Ok(Some(response)),
+                    Ok(_) => {},
+                    Err(error) => eprintln!("Malformed reply from upstream {}: {error}", self.address),
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SelectionPolicy {
+    /// Always try upstreams in the configured order.
+    Failover,
+    /// Start from the next upstream on every query.
+    RoundRobin,
+    /// Start from a randomly chosen upstream.
+    Random,
+    /// Prefer the upstream with the lowest measured round trip time.
+    Fastest,
+}
+
+impl FromStr for SelectionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "failover" => Ok(Self::Failover),
+            "round-robin" => Ok(Self::RoundRobin),
+            "random" => Ok(Self::Random),
+            "fastest" => Ok(Self::Fastest),
+            x => Err(format!("Unknown upstream policy {x}")),
+        }
+    }
+}
+
+
+#[derive(Clone, Default)]
+struct Health {
+    consecutive_failures: u32,
+    down_until: Option<Instant>,
+    round_trip_time: Option<Duration>,
+}
+
+impl Health {
+    fn is_up(&self, now: Instant) -> bool {
+        self.down_until.is_none_or(|until| until <= now)
+    }
+}
+
+
+/// A set of upstream resolvers. Upstreams that keep timing out are marked down
+/// and only tried again once `probe_interval` has passed.
+pub struct UpstreamPool {
+    upstreams: Vec<Upstream>,
+    health: Mutex<Vec<Health>>,
+    policy: SelectionPolicy,
+    next: AtomicUsize,
+    failure_threshold: u32,
+    probe_interval: Duration,
+}
+
+impl UpstreamPool {
+    pub fn new(upstreams: Vec<Upstream>, policy: SelectionPolicy, failure_threshold: u32, probe_interval: Duration) -> Self {
+        let health = vec![Health::default(); upstreams.len()];
+        Self {
+            upstreams,
+            health: Mutex::new(health),
+            policy,
+            next: AtomicUsize::new(0),
+            failure_threshold,
+            probe_interval,
+        }
+    }
+
+    /// Forwards `query` to the upstreams in the order chosen by the policy and
+    /// returns the first reply, with the ID rewritten to match the original
+    /// query. If no upstream answers, a SERVFAIL response is returned.
+    pub fn forward(&self, query: &Message) -> Message {
+        for index in self.candidates() {
+            let upstream = &self.upstreams[index];
+            let start = Instant::now();
+            match upstream.exchange(query) {
+                Ok(Some(response)) => {
+                    self.record_success(index, start.elapsed());
+                    return response.with_id(query.id);
+                },
+                Ok(None) => eprintln!("Upstream {} did not answer", upstream.address),
+                Err(error) => eprintln!("Failed to query upstream {}: {error}", upstream.address),
+            }
+            self.record_failure(index);
+        }
+        Self::failure(query)
+    }
+
+    /// Returns the order in which upstreams should be tried: healthy ones
+    /// first, ordered by the policy, followed by the ones that are down.
+    fn candidates(&self) -> Vec<usize> {
+        let count = self.upstreams.len();
+        if count == 0 {
+            return vec![];
+        }
+        let health = self.health.lock().unwrap();
+        let mut order: Vec<usize> = match self.policy {
+            SelectionPolicy::Failover => (0..count).collect(),
+            SelectionPolicy::RoundRobin => {
+                let start = self.next.fetch_add(1, Ordering::Relaxed);
+                (0..count).map(|i| (start + i) % count).collect()
+            },
+            SelectionPolicy::Random => {
+                let start = random_u64() as usize;
+                (0..count).map(|i| (start + i) % count).collect()
+            },
+            SelectionPolicy::Fastest => {
+                // Upstreams that have not been measured yet sort first, so
+                // every upstream gets a measurement.
+                let mut order: Vec<usize> = (0..count).collect();
+                order.sort_by_key(|&i| health[i].round_trip_time.unwrap_or_default());
+                order
+            },
+        };
+        let now = Instant::now();
+        order.sort_by_key(|&i| !health[i].is_up(now));
+        order
+    }
+
+    fn record_success(&self, index: usize, round_trip_time: Duration) {
+        let mut health = self.health.lock().unwrap();
+        let health = &mut health[index];
+        health.consecutive_failures = 0;
+        health.down_until = None;
+        health.round_trip_time = Some(match health.round_trip_time {
+            Some(previous) => (previous * 7 + round_trip_time) / 8,
+            None => round_trip_time,
+        });
+    }
+
+    fn record_failure(&self, index: usize) {
+        let mut health = self.health.lock().unwrap();
+        let health = &mut health[index];
+        health.consecutive_failures += 1;
+        if health.consecutive_failures >= self.failure_threshold {
+            if health.down_until.is_none() {
+                eprintln!("Marking upstream {} as down", self.upstreams[index].address);
+            }
+            health.down_until = Some(Instant::now() + self.probe_interval);
+        }
+    }
+
+    fn failure(query: &Message) -> Message {
+        Message::new(
+            query.id,
+            Flags::new(
+                false, false, false, query.flags.is_recursion_desired(), true,
+                query.flags.operation(), ResponseCode::ServerFailure
+            ),
+            query.questions.clone(), vec![], vec![], vec![])
+    }
+}
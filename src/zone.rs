@@ -0,0 +1,393 @@
+use std::{
+    fs,
+    mem,
+    net::{Ipv4Addr, Ipv6Addr},
+    path::Path,
+    str::FromStr,
+};
+
+use crate::{error::ZoneError, message::*};
+
+
+/// How deeply `$INCLUDE` directives may nest, which also stops include loops.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+
+/// A zone loaded from a master file, in the format described in RFC 1035
+/// section 5.
+pub struct Zone {
+    pub records: Vec<ResourceRecord>,
+}
+
+impl Zone {
+    /// Loads the master file at `path`. Relative names are taken to be under
+    /// `origin` until the file sets another with `$ORIGIN`.
+    pub fn load(path: &Path, origin: Domain) -> Result<Self, ZoneError> {
+        let mut parser = Parser {
+            origin,
+            default_time_to_live: None,
+            last_time_to_live: None,
+            last_owner: None,
+            records: vec![],
+        };
+        parser.load(path, 0)?;
+        Ok(Self { records: parser.records })
+    }
+}
+
+
+struct Token {
+    text: String,
+    /// Whether the token was written as a quoted string.
+    quoted: bool,
+}
+
+/// One record or directive, which may span several lines in parentheses.
+struct Entry {
+    line: usize,
+    /// Whether the entry started with blank space, leaving out the owner so
+    /// that the previous one applies.
+    inherits_owner: bool,
+    tokens: Vec<Token>,
+}
+
+/// Splits a master file into entries, dropping comments and parentheses.
+/// Errors carry the line they were found on.
+fn split_entries(text: &str) -> Result<Vec<Entry>, (usize, String)> {
+    let mut entries = vec![];
+    let mut entry = Entry { line: 1, inherits_owner: false, tokens: vec![] };
+    let mut line = 1;
+    let mut depth = 0usize;
+    let mut line_start = true;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if line_start && depth == 0 {
+            let next = Entry { line, inherits_owner: c == ' ' || c == '\t', tokens: vec![] };
+            entries.push(mem::replace(&mut entry, next));
+        }
+        line_start = false;
+        match c {
+            '\n' => {
+                line += 1;
+                line_start = true;
+            },
+            ' ' | '\t' | '\r' => {},
+            ';' => while chars.next_if(|&c| c != '\n').is_some() {},
+            '(' => depth += 1,
+            ')' => depth = depth.checked_sub(1).ok_or((line, "Unbalanced closing parenthesis".to_string()))?,
+            '"' => {
+                let start = line;
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => {
+                            text.push('\\');
+                            text.extend(chars.next());
+                        },
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            text.push(c);
+                        },
+                        None => return Err((start, "Unterminated string".into())),
+                    }
+                }
+                entry.tokens.push(Token { text, quoted: true });
+            },
+            c => {
+                let mut text = String::from(c);
+                let mut escaped = c == '\\';
+                while let Some(c) = chars.next_if(|&c| escaped || !(c.is_whitespace() || matches!(c, ';' | '(' | ')' | '"'))) {
+                    escaped = !escaped && c == '\\';
+                    text.push(c);
+                }
+                entry.tokens.push(Token { text, quoted: false });
+            },
+        }
+    }
+    if depth > 0 {
+        return Err((entry.line, "Unbalanced opening parenthesis".into()));
+    }
+    entries.push(entry);
+    entries.retain(|entry| !entry.tokens.is_empty());
+    Ok(entries)
+}
+
+
+struct Parser {
+    origin: Domain,
+    /// The TTL set by `$TTL`, as described in RFC 2308 section 4.
+    default_time_to_live: Option<u32>,
+    /// The last TTL given explicitly, which RFC 1035 uses when one is left out.
+    last_time_to_live: Option<u32>,
+    last_owner: Option<Domain>,
+    records: Vec<ResourceRecord>,
+}
+
+impl Parser {
+    fn load(&mut self, path: &Path, depth: usize) -> Result<(), ZoneError> {
+        let error = |line, message| ZoneError { path: path.to_owned(), line, message };
+        let text = fs::read_to_string(path).map_err(|e| error(None, format!("Failed to read zone file: {e}")))?;
+        let entries = split_entries(&text).map_err(|(line, message)| error(Some(line), message))?;
+        for entry in entries {
+            if !entry.inherits_owner && entry.tokens[0].text == "$INCLUDE" {
+                self.include(path, &entry, depth)?;
+            } else {
+                self.entry(&entry).map_err(|message| error(Some(entry.line), message))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the file named by an `$INCLUDE` entry, relative to the directory
+    /// of the file it appears in. The origin and owner it leaves behind do
+    /// not carry over to the rest of this file.
+    fn include(&mut self, path: &Path, entry: &Entry, depth: usize) -> Result<(), ZoneError> {
+        let error = |message| ZoneError { path: path.to_owned(), line: Some(entry.line), message };
+        let (file, origin) = match &entry.tokens[1..] {
+            [file] => (file, None),
+            [file, origin] => (file, Some(origin)),
+            _ => return Err(error("$INCLUDE takes a file name and an optional origin".into())),
+        };
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(error("$INCLUDE is nested too deeply".into()));
+        }
+
+        let saved = (self.origin.clone(), self.last_owner.clone());
+        if let Some(origin) = origin {
+            self.origin = self.name(&origin.text).map_err(error)?;
+        }
+        self.load(&path.parent().unwrap_or(Path::new(".")).join(&file.text), depth + 1)?;
+        (self.origin, self.last_owner) = saved;
+        Ok(())
+    }
+
+    fn entry(&mut self, entry: &Entry) -> Result<(), String> {
+        match entry.tokens[0].text.as_str() {
+            "$ORIGIN" if !entry.inherits_owner => {
+                let [_, origin] = &entry.tokens[..] else {
+                    return Err("$ORIGIN takes a single name".into());
+                };
+                self.origin = self.name(&origin.text)?;
+            },
+            "$TTL" if !entry.inherits_owner => {
+                let [_, time_to_live] = &entry.tokens[..] else {
+                    return Err("$TTL takes a single TTL".into());
+                };
+                self.default_time_to_live = Some(parse_time_to_live(&time_to_live.text)?);
+            },
+            directive if directive.starts_with('$') && !entry.inherits_owner => {
+                return Err(format!("Unknown directive {directive}"));
+            },
+            _ => self.record(entry)?,
+        }
+        Ok(())
+    }
+
+    /// Parses `[owner] [ttl] [class] type data`, where the TTL and class may
+    /// come in either order.
+    fn record(&mut self, entry: &Entry) -> Result<(), String> {
+        let mut tokens = &entry.tokens[..];
+        let owner = if entry.inherits_owner {
+            self.last_owner.clone().ok_or("No owner given for the first record")?
+        } else {
+            let owner = self.name(&tokens[0].text)?;
+            tokens = &tokens[1..];
+            owner
+        };
+
+        let mut time_to_live = None;
+        let mut has_class = false;
+        let typ = loop {
+            let Some((token, rest)) = tokens.split_first() else {
+                return Err("Missing record type".into());
+            };
+            tokens = rest;
+            if time_to_live.is_none() && token.text.starts_with(|c: char| c.is_ascii_digit()) {
+                time_to_live = Some(parse_time_to_live(&token.text)?);
+            } else if !has_class && token.text.eq_ignore_ascii_case("IN") {
+                has_class = true;
+            } else if ["CH", "CS", "HS"].iter().any(|class| token.text.eq_ignore_ascii_case(class)) {
+                return Err(format!("Unsupported class {}", token.text));
+            } else {
+                break token.text.parse::<ResourceRecordType>()?;
+            }
+        };
+
+        let data = self.record_data(&typ, tokens)?;
+        if time_to_live.is_some() {
+            self.last_time_to_live = time_to_live;
+        }
+        let time_to_live = time_to_live
+            .or(self.default_time_to_live)
+            .or(self.last_time_to_live)
+            .or(match &data {
+                ResourceRecordData::SOA(soa) => Some(soa.minimum),
+                _ => None,
+            })
+            .ok_or("No TTL given and no $TTL set")?;
+        self.last_owner = Some(owner.clone());
+        self.records.push(ResourceRecord::new(owner, Class::Internet, time_to_live, data));
+        Ok(())
+    }
+
+    fn record_data(&self, typ: &ResourceRecordType, tokens: &[Token]) -> Result<ResourceRecordData, String> {
+        let text: Vec<&str> = tokens.iter().map(|token| token.text.as_str()).collect();
+        if tokens.first().is_some_and(|token| !token.quoted && token.text == "\\#") {
+            return ResourceRecordData::from_generic(typ.clone(), &text.join(" "));
+        }
+        let fields = |count: usize| if text.len() == count {
+            Ok(())
+        } else {
+            Err(format!("Expected {count} fields of {typ:?} data, found {}", text.len()))
+        };
+
+        Ok(match typ {
+            ResourceRecordType::A => {
+                fields(1)?;
+                let [a, b, c, d] = parse::<Ipv4Addr>(text[0])?.octets();
+                ResourceRecordData::A(IPV4(a, b, c, d))
+            },
+            ResourceRecordType::AAAA => {
+                fields(1)?;
+                ResourceRecordData::AAAA(IPV6(parse::<Ipv6Addr>(text[0])?.segments()))
+            },
+            ResourceRecordType::NS => {
+                fields(1)?;
+                ResourceRecordData::NS(self.name(text[0])?)
+            },
+            ResourceRecordType::CNAME => {
+                fields(1)?;
+                ResourceRecordData::CNAME(self.name(text[0])?)
+            },
+            ResourceRecordType::PTR => {
+                fields(1)?;
+                ResourceRecordData::PTR(self.name(text[0])?)
+            },
+            ResourceRecordType::DNAME => {
+                fields(1)?;
+                ResourceRecordData::DNAME(self.name(text[0])?)
+            },
+            ResourceRecordType::MX => {
+                fields(2)?;
+                ResourceRecordData::MX(MailExchange { preference: parse(text[0])?, exchange: self.name(text[1])? })
+            },
+            ResourceRecordType::SOA => {
+                fields(7)?;
+                ResourceRecordData::SOA(StartOfAuthority {
+                    primary: self.name(text[0])?,
+                    mailbox: self.name(text[1])?,
+                    serial: parse(text[2])?,
+                    refresh: parse_time_to_live(text[3])?,
+                    retry: parse_time_to_live(text[4])?,
+                    expire: parse_time_to_live(text[5])?,
+                    minimum: parse_time_to_live(text[6])?,
+                })
+            },
+            ResourceRecordType::TXT => {
+                if text.is_empty() {
+                    return Err("Expected at least one string of TXT data".into());
+                }
+                ResourceRecordData::TXT(text.iter()
+                    .map(|text| match unescape(text)? {
+                        string if string.len() > 255 => Err(format!("String {text} is longer than 255 bytes")),
+                        string => Ok(string),
+                    })
+                    .collect::<Result<_, _>>()?)
+            },
+            ResourceRecordType::SRV => {
+                fields(4)?;
+                ResourceRecordData::SRV(Service {
+                    priority: parse(text[0])?,
+                    weight: parse(text[1])?,
+                    port: parse(text[2])?,
+                    target: self.name(text[3])?,
+                })
+            },
+            ResourceRecordType::CAA => {
+                fields(3)?;
+                ResourceRecordData::CAA(CertificationAuthority {
+                    flags: parse(text[0])?,
+                    tag: text[1].to_string(),
+                    value: unescape(text[2])?,
+                })
+            },
+            typ => return Err(format!("{typ:?} data must be given in the generic \\# form")),
+        })
+    }
+
+    /// Resolves a name as written in the file: `@` is the origin, and names
+    /// without a trailing dot are relative to it.
+    fn name(&self, text: &str) -> Result<Domain, String> {
+        if text == "@" {
+            return Ok(self.origin.clone());
+        }
+        let name: Domain = text.parse()?;
+        if text.ends_with('.') {
+            return Ok(name);
+        }
+        Domain::new([name.0, self.origin.0.clone()].concat())
+    }
+}
+
+fn parse<T: FromStr>(text: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("Invalid value {text}"))
+}
+
+/// Parses a TTL in seconds, or written with BIND's units as in `1h30m`.
+pub fn parse_time_to_live(text: &str) -> Result<u32, String> {
+    if let Ok(seconds) = text.parse() {
+        return Ok(seconds);
+    }
+    let invalid = || format!("Invalid TTL {text}");
+    let mut total: u32 = 0;
+    let mut digits = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        let value: u32 = digits.parse().map_err(|_| invalid())?;
+        total = value.checked_mul(unit).and_then(|value| total.checked_add(value)).ok_or_else(invalid)?;
+        digits.clear();
+    }
+    if !digits.is_empty() {
+        return Err(invalid());
+    }
+    Ok(total)
+}
+
+/// Turns the escapes in a character string, `\X` and `\DDD`, into the bytes
+/// they stand for.
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            bytes.extend(c.to_string().as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some(c) if c.is_ascii_digit() => {
+                let digits: String = [Some(c), chars.next(), chars.next()].into_iter().flatten().collect();
+                let byte = digits.parse().ok()
+                    .filter(|_| digits.len() == 3)
+                    .ok_or_else(|| format!("Invalid escape \\{digits} in {text}"))?;
+                bytes.push(byte);
+            },
+            Some(c) => bytes.extend(c.to_string().as_bytes()),
+            None => return Err(format!("Dangling escape in {text}")),
+        }
+    }
+    Ok(bytes)
+}
@@ -0,0 +1,349 @@
+use miadon::{cache::{Answer, HostCache}, message::*};
+
+
+fn name(text: &str) -> Domain {
+    text.parse().unwrap()
+}
+
+fn cache() -> HostCache {
+    HostCache::new(0, 86400, 10800, 1000, 1 << 20)
+}
+
+fn question(text: &str, typ: ResourceRecordType) -> Question {
+    Question { name: name(text), typ, class: Class::Internet }
+}
+
+fn a(text: &str, ip: IPV4) -> ResourceRecord {
+    ResourceRecord::new(name(text), Class::Internet, 300, ResourceRecordData::A(ip))
+}
+
+fn soa(text: &str) -> ResourceRecord {
+    soa_with(text, 300, 300)
+}
+
+fn soa_with(text: &str, time_to_live: u32, minimum: u32) -> ResourceRecord {
+    ResourceRecord::new(name(text), Class::Internet, time_to_live, ResourceRecordData::SOA(StartOfAuthority {
+        primary: name("ns.example"),
+        mailbox: name("hostmaster.example"),
+        serial: 1, refresh: 3600, retry: 600, expire: 86400, minimum,
+    }))
+}
+
+fn response(question: Question, response_code: ResponseCode, answers: Vec<ResourceRecord>, authority: Vec<ResourceRecord>) -> Message {
+    Message::new(
+        1,
+        Flags::new(false, false, false, true, true, Operation::Query, response_code),
+        vec![question], answers, authority, vec![])
+}
+
+fn addresses(answer: &Answer) -> Vec<(String, [u8; 4])> {
+    answer.records.iter()
+        .filter_map(|record| match &record.data {
+            ResourceRecordData::A(IPV4(a, b, c, d)) => Some((record.name.to_string(), [*a, *b, *c, *d])),
+            _ => None,
+        })
+        .collect()
+}
+
+
+#[test]
+fn store_drops_answers_for_unrelated_names() {
+    let cache = cache();
+    cache.store(&response(
+        question("ext.example", ResourceRecordType::A), ResponseCode::NoError,
+        vec![a("ext.example", IPV4(192, 0, 2, 1)), a("localhost", IPV4(6, 6, 6, 6))], vec![],
+    ));
+
+    let answer = cache.handle_question(&question("ext.example", ResourceRecordType::A)).unwrap();
+    assert_eq!(addresses(&answer), vec![("ext.example.".to_string(), [192, 0, 2, 1])]);
+    assert!(cache.handle_question(&question("localhost", ResourceRecordType::A)).is_none());
+}
+
+#[test]
+fn store_keeps_the_cname_chain() {
+    let cache = cache();
+    cache.store(&response(
+        question("www.ext.example", ResourceRecordType::A), ResponseCode::NoError,
+        vec![
+            ResourceRecord::new(name("www.ext.example"), Class::Internet, 300, ResourceRecordData::CNAME(name("cdn.other.example"))),
+            a("cdn.other.example", IPV4(192, 0, 2, 7)),
+            a("bank.example", IPV4(6, 6, 6, 6)),
+        ],
+        vec![],
+    ));
+
+    let answer = cache.handle_question(&question("cdn.other.example", ResourceRecordType::A)).unwrap();
+    assert_eq!(addresses(&answer), vec![("cdn.other.example.".to_string(), [192, 0, 2, 7])]);
+    assert!(cache.handle_question(&question("bank.example", ResourceRecordType::A)).is_none());
+}
+
+#[test]
+fn store_ignores_negative_answers_from_unrelated_zones() {
+    let cache = cache();
+    cache.store(&response(
+        question("missing.ext.example", ResourceRecordType::A), ResponseCode::NonExistentDomain,
+        vec![], vec![soa("other.example")],
+    ));
+    assert!(cache.handle_question(&question("missing.ext.example", ResourceRecordType::A)).is_none());
+
+    cache.store(&response(
+        question("missing.ext.example", ResourceRecordType::A), ResponseCode::NonExistentDomain,
+        vec![], vec![soa("ext.example")],
+    ));
+    let answer = cache.handle_question(&question("missing.ext.example", ResourceRecordType::A)).unwrap();
+    assert!(matches!(answer.response_code, ResponseCode::NonExistentDomain));
+}
+
+#[test]
+fn store_leaves_owned_zones_alone() {
+    let mut cache = cache();
+    cache.add_zone(name("z.test"), vec![
+        soa("z.test"),
+        ResourceRecord::new(name("z.test"), Class::Internet, 300, ResourceRecordData::NS(name("ns.z.test"))),
+        a("ns.z.test", IPV4(192, 0, 2, 53)),
+        a("*.z.test", IPV4(192, 0, 2, 80)),
+    ]).unwrap();
+    cache.store(&response(
+        question("ext.example", ResourceRecordType::A), ResponseCode::NoError,
+        vec![
+            ResourceRecord::new(name("ext.example"), Class::Internet, 300, ResourceRecordData::CNAME(name("foo.z.test"))),
+            a("foo.z.test", IPV4(6, 6, 6, 6)),
+        ],
+        vec![],
+    ));
+    cache.store(&response(
+        question("bar.z.test", ResourceRecordType::A), ResponseCode::NonExistentDomain,
+        vec![], vec![soa("z.test")],
+    ));
+
+    for owner in ["foo.z.test", "bar.z.test"] {
+        let answer = cache.handle_question(&question(owner, ResourceRecordType::A)).unwrap();
+        assert!(answer.is_authoritative);
+        assert_eq!(addresses(&answer), vec![(format!("{owner}."), [192, 0, 2, 80])]);
+    }
+    let answer = cache.handle_question(&question("foo.bar.y.z.test", ResourceRecordType::A)).unwrap();
+    assert_eq!(addresses(&answer), vec![("foo.bar.y.z.test.".to_string(), [192, 0, 2, 80])]);
+}
+
+#[test]
+fn learned_names_do_not_hide_local_wildcards() {
+    let mut cache = cache();
+    cache.bind(name("*.preview.corp"), 60, vec![ResourceRecordData::A(IPV4(10, 0, 0, 1))]);
+    cache.bind(name("a.b.preview.corp"), 60, vec![ResourceRecordData::A(IPV4(10, 0, 0, 2))]);
+    cache.store(&response(
+        question("foo.preview.corp", ResourceRecordType::AAAA), ResponseCode::NonExistentDomain,
+        vec![], vec![soa("corp")],
+    ));
+    cache.store(&response(
+        question("b.x.preview.corp", ResourceRecordType::A), ResponseCode::NoError,
+        vec![a("b.x.preview.corp", IPV4(6, 6, 6, 6))], vec![],
+    ));
+    cache.store(&response(
+        question("b.preview.corp", ResourceRecordType::A), ResponseCode::NonExistentDomain,
+        vec![], vec![soa("corp")],
+    ));
+
+    for owner in ["foo.preview.corp", "x.preview.corp", "b.x.preview.corp"] {
+        let answer = cache.handle_question(&question(owner, ResourceRecordType::A)).unwrap();
+        assert_eq!(addresses(&answer), vec![(format!("{owner}."), [10, 0, 0, 1])]);
+    }
+    assert!(cache.handle_question(&question("foo.preview.corp", ResourceRecordType::AAAA)).is_none());
+    // `b.preview.corp` exists locally, with a name below it, so neither the
+    // wildcard nor an NXDOMAIN from upstream applies to it.
+    assert!(cache.handle_question(&question("b.preview.corp", ResourceRecordType::A)).is_none());
+}
+
+#[test]
+fn least_recently_used_names_are_evicted_first() {
+    let cache = HostCache::new(0, 86400, 10800, 3, 1 << 20);
+    let store = |owner: &str| cache.store(&response(
+        question(owner, ResourceRecordType::A), ResponseCode::NoError, vec![a(owner, IPV4(192, 0, 2, 1))], vec![],
+    ));
+    let cached = |owner: &str| cache.handle_question(&question(owner, ResourceRecordType::A)).is_some();
+    for owner in ["a.example", "b.example", "c.example"] {
+        store(owner);
+    }
+    // A hit makes `a.example` the most recently used, leaving `b.example` to go.
+    assert!(cached("a.example"));
+    store("d.example");
+    assert_eq!(["a.example", "b.example", "c.example", "d.example"].map(cached), [true, false, true, true]);
+
+    let statistics = cache.statistics();
+    assert_eq!((statistics.hits, statistics.misses, statistics.evictions), (4, 1, 1));
+    assert_eq!(statistics.names, 3);
+}
+
+#[test]
+fn learned_records_are_kept_within_the_size_limit() {
+    let store = |cache: &HostCache, owner: &str| cache.store(&response(
+        question(owner, ResourceRecordType::A), ResponseCode::NoError, vec![a(owner, IPV4(192, 0, 2, 1))], vec![],
+    ));
+    let cache = cache();
+    store(&cache, "a.example");
+    let size = cache.statistics().size;
+    assert!(size > 0);
+
+    let cache = HostCache::new(0, 86400, 10800, 1000, size * 2);
+    for owner in ["a.example", "b.example", "c.example"] {
+        store(&cache, owner);
+    }
+    let statistics = cache.statistics();
+    assert_eq!((statistics.names, statistics.size, statistics.evictions), (2, size * 2, 1));
+    assert!(cache.handle_question(&question("a.example", ResourceRecordType::A)).is_none());
+    assert!(cache.handle_question(&question("c.example", ResourceRecordType::A)).is_some());
+}
+
+#[test]
+fn random_subdomain_floods_do_not_push_out_names_in_use() {
+    let cache = HostCache::new(0, 86400, 10800, 100, 1 << 20);
+    cache.store(&response(
+        question("www.example", ResourceRecordType::A), ResponseCode::NoError, vec![a("www.example", IPV4(192, 0, 2, 1))], vec![],
+    ));
+    for i in 0..1000 {
+        let owner = format!("r{i}.flood.example");
+        cache.store(&response(
+            question(&owner, ResourceRecordType::A), ResponseCode::NonExistentDomain, vec![], vec![soa("flood.example")],
+        ));
+        if i % 50 == 0 {
+            assert!(cache.handle_question(&question("www.example", ResourceRecordType::A)).is_some());
+        }
+    }
+    let statistics = cache.statistics();
+    assert_eq!((statistics.names, statistics.evictions), (100, 901));
+    assert!(cache.handle_question(&question("www.example", ResourceRecordType::A)).is_some());
+    assert!(cache.handle_question(&question("r0.flood.example", ResourceRecordType::A)).is_none());
+    assert!(cache.handle_question(&question("r999.flood.example", ResourceRecordType::A)).is_some());
+}
+
+/// The TTL a negative answer hands out for its SOA, which may already have
+/// counted down by a second.
+fn negative_time_to_live(answer: &Answer) -> u32 {
+    assert!(answer.records.is_empty());
+    assert!(matches!(answer.authority[..], [ResourceRecord { data: ResourceRecordData::SOA(_), .. }]));
+    answer.authority[0].time_to_live
+}
+
+#[test]
+fn negative_answers_last_for_the_lesser_of_the_soa_ttl_and_minimum() {
+    let cache = cache();
+    for (owner, time_to_live, minimum, expected) in [("a.ext.example", 600, 120, 120), ("b.ext.example", 90, 3600, 90)] {
+        cache.store(&response(
+            question(owner, ResourceRecordType::A), ResponseCode::NonExistentDomain,
+            vec![], vec![soa_with("ext.example", time_to_live, minimum)],
+        ));
+        let answer = cache.handle_question(&question(owner, ResourceRecordType::A)).unwrap();
+        assert!(matches!(answer.response_code, ResponseCode::NonExistentDomain));
+        assert!((expected - 1..=expected).contains(&negative_time_to_live(&answer)));
+    }
+
+    let cache = HostCache::new(0, 86400, 60, 1000, 1 << 20);
+    cache.store(&response(
+        question("c.ext.example", ResourceRecordType::A), ResponseCode::NonExistentDomain,
+        vec![], vec![soa_with("ext.example", 3600, 3600)],
+    ));
+    let answer = cache.handle_question(&question("c.ext.example", ResourceRecordType::A)).unwrap();
+    assert!((59..=60).contains(&negative_time_to_live(&answer)));
+}
+
+#[test]
+fn nodata_only_answers_its_own_type() {
+    let cache = cache();
+    cache.store(&response(
+        question("www.ext.example", ResourceRecordType::AAAA), ResponseCode::NoError, vec![], vec![soa("ext.example")],
+    ));
+    let answer = cache.handle_question(&question("www.ext.example", ResourceRecordType::AAAA)).unwrap();
+    assert!(matches!(answer.response_code, ResponseCode::NoError));
+    negative_time_to_live(&answer);
+    assert!(cache.handle_question(&question("www.ext.example", ResourceRecordType::A)).is_none());
+
+    cache.store(&response(
+        question("www.ext.example", ResourceRecordType::A), ResponseCode::NoError,
+        vec![a("www.ext.example", IPV4(192, 0, 2, 1))], vec![],
+    ));
+    let answer = cache.handle_question(&question("www.ext.example", ResourceRecordType::A)).unwrap();
+    assert_eq!(addresses(&answer), vec![("www.ext.example.".to_string(), [192, 0, 2, 1])]);
+    assert!(cache.handle_question(&question("www.ext.example", ResourceRecordType::AAAA)).is_some());
+}
+
+#[test]
+fn positive_answers_replace_a_cached_nxdomain() {
+    let cache = cache();
+    cache.store(&response(
+        question("new.ext.example", ResourceRecordType::A), ResponseCode::NonExistentDomain, vec![], vec![soa("ext.example")],
+    ));
+    cache.store(&response(
+        question("new.ext.example", ResourceRecordType::A), ResponseCode::NoError,
+        vec![a("new.ext.example", IPV4(192, 0, 2, 9))], vec![],
+    ));
+    let answer = cache.handle_question(&question("new.ext.example", ResourceRecordType::A)).unwrap();
+    assert!(matches!(answer.response_code, ResponseCode::NoError));
+    assert_eq!(addresses(&answer), vec![("new.ext.example.".to_string(), [192, 0, 2, 9])]);
+    // The name exists after all, so nothing is known about its other types.
+    assert!(cache.handle_question(&question("new.ext.example", ResourceRecordType::AAAA)).is_none());
+}
+
+fn zone() -> HostCache {
+    let mut cache = cache();
+    let ns = |owner: &str, server: &str| ResourceRecord::new(name(owner), Class::Internet, 300, ResourceRecordData::NS(name(server)));
+    cache.add_zone(name("z.test"), vec![
+        soa_with("z.test", 600, 120),
+        ns("z.test", "ns.z.test"),
+        a("ns.z.test", IPV4(192, 0, 2, 53)),
+        a("www.z.test", IPV4(192, 0, 2, 80)),
+        ns("sub.z.test", "ns.sub.z.test"),
+        a("ns.sub.z.test", IPV4(192, 0, 2, 54)),
+    ]).unwrap();
+    cache
+}
+
+fn types(records: &[ResourceRecord]) -> Vec<(String, ResourceRecordType)> {
+    records.iter().map(|record| (record.name.to_string(), ResourceRecordType::from(&record.data))).collect()
+}
+
+#[test]
+fn authoritative_answers_carry_the_zone_name_servers() {
+    let cache = zone();
+    let answer = cache.handle_question(&question("www.z.test", ResourceRecordType::A)).unwrap();
+    assert!(answer.is_authoritative);
+    assert!(matches!(answer.response_code, ResponseCode::NoError));
+    assert_eq!(addresses(&answer), vec![("www.z.test.".to_string(), [192, 0, 2, 80])]);
+    assert_eq!(types(&answer.authority), [("z.test.".to_string(), ResourceRecordType::NS)]);
+    assert_eq!(types(&answer.additional), [("ns.z.test.".to_string(), ResourceRecordType::A)]);
+
+    for typ in [ResourceRecordType::NS, ResourceRecordType::Any] {
+        let answer = cache.handle_question(&question("z.test", typ)).unwrap();
+        assert!(answer.is_authoritative);
+        assert_eq!(answer.records.iter().filter(|record| matches!(record.data, ResourceRecordData::NS(_))).count(), 1);
+        assert!(answer.authority.is_empty());
+        assert_eq!(types(&answer.additional), [("ns.z.test.".to_string(), ResourceRecordType::A)]);
+    }
+}
+
+#[test]
+fn missing_names_and_types_get_the_zone_soa() {
+    let cache = zone();
+    for (owner, typ, response_code) in [
+        ("missing.z.test", ResourceRecordType::A, ResponseCode::NonExistentDomain),
+        ("www.z.test", ResourceRecordType::AAAA, ResponseCode::NoError),
+    ] {
+        let answer = cache.handle_question(&question(owner, typ)).unwrap();
+        assert!(answer.is_authoritative);
+        assert_eq!(u16::from(answer.response_code.clone()), u16::from(response_code));
+        assert!(answer.records.is_empty());
+        assert_eq!(types(&answer.authority), [("z.test.".to_string(), ResourceRecordType::SOA)]);
+        assert_eq!(answer.authority[0].time_to_live, 120);
+    }
+}
+
+#[test]
+fn names_below_a_delegation_get_a_referral() {
+    let cache = zone();
+    for owner in ["sub.z.test", "host.sub.z.test"] {
+        let answer = cache.handle_question(&question(owner, ResourceRecordType::A)).unwrap();
+        assert!(!answer.is_authoritative);
+        assert!(matches!(answer.response_code, ResponseCode::NoError));
+        assert!(answer.records.is_empty());
+        assert_eq!(types(&answer.authority), [("sub.z.test.".to_string(), ResourceRecordType::NS)]);
+        assert_eq!(types(&answer.additional), [("ns.sub.z.test.".to_string(), ResourceRecordType::A)]);
+    }
+}
@@ -0,0 +1,344 @@
+use miadon::{
+    error::DecodeError,
+    message::*,
+    streams::{SliceReader, VecWriter},
+};
+
+
+/// A query from the glibc stub resolver for `www.example.com A`.
+const STUB_QUERY: &str = "
+    3e1f 0100 0001 0000 0000 0000
+    03 777777 07 6578616d706c65 03 636f6d 00 0001 0001
+";
+
+/// A query for `example.com AAAA` with an OPT record carrying a client cookie.
+const EDNS_COOKIE_QUERY: &str = "
+    8b2a 0100 0001 0000 0000 0001
+    07 6578616d706c65 03 636f6d 00 001c 0001
+    00 0029 04d0 00000000 000c 000a 0008 5d1a6f0c93e2b847
+";
+
+/// A response for `www.github.com A` that goes through a CNAME, with the
+/// target pointing back into the question.
+const CNAME_RESPONSE: &str = "
+    5c07 8180 0001 0002 0000 0001
+    03 777777 06 676974687562 03 636f6d 00 0001 0001
+    c00c 0005 0001 00000e10 0002 c010
+    c010 0001 0001 0000003c 0004 8c527904
+    00 0029 04d0 00000000 0000
+";
+
+/// An NXDOMAIN for `nope.example.com A`, with the zone's SOA in the authority
+/// section and its RNAME compressed against its MNAME.
+const NXDOMAIN_RESPONSE: &str = "
+    d1e4 8183 0001 0000 0001 0000
+    04 6e6f7065 07 6578616d706c65 03 636f6d 00 0001 0001
+    c011 0006 0001 00000e10 002c
+        02 6e73 05 6963616e6e 03 6f7267 00
+        03 6e6f63 03 646e73 c031
+        78a50840 00001c20 00000e10 00127500 00000e10
+";
+
+/// An authoritative response for `example.org MX`, with addresses for the
+/// first exchange in the additional section.
+const MX_RESPONSE: &str = "
+    1f60 8580 0001 0002 0000 0002
+    07 6578616d706c65 03 6f7267 00 000f 0001
+    c00c 000f 0001 0000012c 0009 000a 04 6d61696c c00c
+    c00c 000f 0001 0000012c 000b 0014 06 6261636b7570 c00c
+    c02b 0001 0001 0000012c 0004 5db8d70e
+    c02b 001c 0001 0000012c 0010 26062800021f0000000000000000000e
+";
+
+/// A response for `_sip._tcp.example.com ANY` holding an SRV record, whose
+/// target is never compressed, and a TXT record with two strings.
+const SRV_TXT_RESPONSE: &str = "
+    0a0b 8180 0001 0002 0000 0000
+    04 5f736970 04 5f746370 07 6578616d706c65 03 636f6d 00 00ff 0001
+    c00c 0021 0001 00015180 0017 000a 003c 13c4 03 736970 07 6578616d706c65 03 636f6d 00
+    c00c 0010 0001 00015180 0012 0b 763d73706631202d616c6c 05 68656c6c6f
+";
+
+/// A response from a validating resolver for `example.com A` with the DO bit,
+/// so it has AD set, to a query that had CD set.
+const AUTHENTIC_RESPONSE: &str = "
+    77c3 81b0 0001 0001 0000 0001
+    07 6578616d706c65 03 636f6d 00 0001 0001
+    c00c 0001 0001 00000e10 0004 5db8d70e
+    00 0029 04d0 00008000 0000
+";
+
+const PACKETS: [&str; 7] = [
+    STUB_QUERY, EDNS_COOKIE_QUERY, CNAME_RESPONSE, NXDOMAIN_RESPONSE, MX_RESPONSE, SRV_TXT_RESPONSE,
+    AUTHENTIC_RESPONSE,
+];
+
+
+fn packet(hex: &str) -> Vec<u8> {
+    let hex: String = hex.split_whitespace().collect();
+    (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+fn decode(bytes: &[u8]) -> Result<Message, DecodeError> {
+    Message::read_from_stream(&mut SliceReader::new(bytes))
+}
+
+fn encode(message: &Message) -> Vec<u8> {
+    let mut buffer = VecWriter::new();
+    message.write_to_stream(&mut buffer);
+    buffer.bytes().to_vec()
+}
+
+fn encode_flags(flags: &Flags) -> u16 {
+    let mut buffer = VecWriter::new();
+    flags.write_to_stream(&mut buffer);
+    u16::from_be_bytes(buffer.bytes().try_into().unwrap())
+}
+
+fn decode_flags(word: u16) -> Result<Flags, DecodeError> {
+    Flags::read_from_stream(&mut SliceReader::new(&word.to_be_bytes()))
+}
+
+fn operations() -> Vec<Operation> {
+    (0..16u16).filter_map(|x| x.try_into().ok()).collect()
+}
+
+fn response_codes() -> Vec<ResponseCode> {
+    (0..4096u16).filter_map(|x| x.try_into().ok()).collect()
+}
+
+
+#[test]
+fn golden_packets_round_trip() {
+    for hex in PACKETS {
+        let bytes = packet(hex);
+        let message = decode(&bytes).unwrap_or_else(|error| panic!("{error} decoding {hex}"));
+        assert_eq!(encode(&message), bytes, "re-encoding {hex}");
+        assert_eq!(message.encoded_len(), bytes.len());
+    }
+}
+
+#[test]
+fn golden_packets_decode_fields() {
+    let query = decode(&packet(STUB_QUERY)).unwrap();
+    assert_eq!(query.id, 0x3e1f);
+    assert!(query.flags.is_recursion_desired());
+    assert_eq!(query.questions, vec![Question {
+        name: "www.example.com".parse().unwrap(),
+        typ: ResourceRecordType::A,
+        class: Class::Internet,
+    }]);
+    assert!(query.edns.is_none());
+
+    let query = decode(&packet(EDNS_COOKIE_QUERY)).unwrap();
+    let edns = query.edns.unwrap();
+    assert_eq!(edns.udp_size, 1232);
+    assert_eq!(edns.options.len(), 1);
+    assert_eq!(edns.options[0].code, 10);
+    assert!(query.additional_records.is_empty());
+
+    let response = decode(&packet(CNAME_RESPONSE)).unwrap();
+    assert!(matches!(&response.answers[0].data, ResourceRecordData::CNAME(target) if target.to_string() == "github.com."));
+    assert_eq!(response.answers[1].name.to_string(), "github.com.");
+    assert!(matches!(response.answers[1].data, ResourceRecordData::A(IPV4(140, 82, 121, 4))));
+
+    let response = decode(&packet(NXDOMAIN_RESPONSE)).unwrap();
+    assert!(matches!(response.flags.response_code(), ResponseCode::NonExistentDomain));
+    let ResourceRecordData::SOA(soa) = &response.authoritative_records[0].data else {
+        panic!("expected an SOA record");
+    };
+    assert_eq!(soa.mailbox.to_string(), "noc.dns.icann.org.");
+    assert_eq!(soa.minimum, 3600);
+
+    let response = decode(&packet(MX_RESPONSE)).unwrap();
+    assert_eq!(response.additional_records[0].name.to_string(), "mail.example.org.");
+    assert!(matches!(&response.additional_records[1].data, ResourceRecordData::AAAA(IPV6(segments)) if segments[7] == 0xe));
+    assert!(!response.flags.is_authentic_data());
+
+    let response = decode(&packet(AUTHENTIC_RESPONSE)).unwrap();
+    assert!(response.flags.is_authentic_data());
+    assert!(response.flags.is_checking_disabled());
+    assert!(response.edns.unwrap().dnssec_ok);
+}
+
+#[test]
+fn truncated_packets_fail_cleanly() {
+    for hex in PACKETS {
+        let bytes = packet(hex);
+        for len in 0..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err(), "decoding {len} bytes of {hex}");
+        }
+    }
+}
+
+#[test]
+fn flags_encode_every_field() {
+    for operation in operations() {
+        for response_code in response_codes().into_iter().filter(|code| u16::from(code.clone()) < 16) {
+            for bits in 0..128u16 {
+                let bit = |n: u16| bits & (1 << n) != 0;
+                let mut flags = Flags::new(
+                    bit(0), bit(1), bit(2), bit(3), bit(4),
+                    operation.clone(), response_code.clone()
+                );
+                flags.set_authentic_data(bit(5));
+                flags.set_checking_disabled(bit(6));
+                let expected = (!bit(0) as u16) << 15
+                    | u16::from(operation.clone()) << 11
+                    | (bit(1) as u16) << 10
+                    | (bit(2) as u16) << 9
+                    | (bit(3) as u16) << 8
+                    | (bit(4) as u16) << 7
+                    | (bit(5) as u16) << 5
+                    | (bit(6) as u16) << 4
+                    | u16::from(response_code.clone());
+                assert_eq!(encode_flags(&flags), expected);
+
+                let decoded = decode_flags(expected).unwrap();
+                assert_eq!(u16::from(decoded.operation()), u16::from(operation.clone()));
+                assert_eq!(u16::from(decoded.response_code()), u16::from(response_code.clone()));
+                assert_eq!(decoded.is_truncated(), bit(2));
+                assert_eq!(decoded.is_recursion_desired(), bit(3));
+                assert_eq!(decoded.is_authentic_data(), bit(5));
+                assert_eq!(decoded.is_checking_disabled(), bit(6));
+            }
+        }
+    }
+}
+
+#[test]
+fn flags_decode_every_word() {
+    for word in 0..=u16::MAX {
+        match decode_flags(word) {
+            Ok(flags) => assert_eq!(encode_flags(&flags), word, "flags {word:#06x}"),
+            Err(DecodeError::UnknownOperation(x)) => assert_eq!(x, (word >> 11) & 0xf),
+            Err(DecodeError::UnknownResponseCode(x)) => assert_eq!(x, word & 0xf),
+            Err(error) => panic!("unexpected {error} decoding flags {word:#06x}"),
+        }
+    }
+}
+
+#[test]
+fn extended_response_codes_round_trip_through_edns() {
+    for response_code in response_codes() {
+        let mut message = Message::new(
+            0x1234,
+            Flags::new(false, false, false, true, true, Operation::Query, response_code.clone()),
+            vec![], vec![], vec![], vec![]);
+        message.edns = Some(Edns::new(1232, false));
+        let decoded = decode(&encode(&message)).unwrap();
+        assert_eq!(u16::from(decoded.flags.response_code()), u16::from(response_code));
+    }
+}
+
+#[test]
+fn labels_keep_case_but_compare_without_it() {
+    let bytes = packet("
+        0001 0100 0001 0000 0000 0000
+        03 575757 07 4578416d506c45 03 636f4d 00 0001 0001
+    ");
+    let query = decode(&bytes).unwrap();
+    assert_eq!(encode(&query), bytes);
+    assert_eq!(query.questions[0].name.to_string(), "WWW.ExAmPlE.coM.");
+    assert_eq!(query.questions[0].name, "www.example.com".parse().unwrap());
+}
+
+#[test]
+fn names_escape_bytes_in_presentation_format() {
+    let name: Domain = r"a\.b.\000\255\\.example".parse().unwrap();
+    assert_eq!(name.0.len(), 3);
+    assert_eq!(name.0[0].as_bytes(), b"a.b");
+    assert_eq!(name.0[1].as_bytes(), b"\x00\xff\\");
+    assert_eq!(name.to_string(), r"a\.b.\000\255\\.example.");
+    assert_eq!(name.to_string().parse::<Domain>().unwrap(), name);
+
+    let mut buffer = VecWriter::new();
+    name.write_uncompressed(&mut buffer);
+    assert_eq!(buffer.bytes(), packet("03 612e62 03 00ff5c 07 6578616d706c65 00"));
+}
+
+#[test]
+fn invalid_names_are_rejected() {
+    assert!("a..b".parse::<Domain>().is_err());
+    assert!("caf\u{e9}.example".parse::<Domain>().is_err());
+    assert!(r"\256.example".parse::<Domain>().is_err());
+    assert!(r"\12".parse::<Domain>().is_err());
+    assert!(format!("{}.example", "a".repeat(64)).parse::<Domain>().is_err());
+    assert!(format!("{}.example", "a".repeat(63)).parse::<Domain>().is_ok());
+    assert!(vec!["a".repeat(63); 4].join(".").parse::<Domain>().is_err());
+    assert!(vec!["a".repeat(63); 3].join(".").parse::<Domain>().is_ok());
+    assert!(Label::new(&[b'a'; 64]).is_err());
+}
+
+#[test]
+fn generic_record_data_is_decoded_for_known_types() {
+    let generic = |typ: &str, text: &str| ResourceRecordData::from_generic(typ.parse().unwrap(), text);
+    assert!(matches!(generic("A", r"\# 4 c0000201"), Ok(ResourceRecordData::A(IPV4(192, 0, 2, 1)))));
+    assert!(matches!(generic("CNAME", r"\# 13 07 6578616d706c65 03 636f6d 00"),
+        Ok(ResourceRecordData::CNAME(target)) if target.to_string() == "example.com."));
+    assert!(matches!(generic("TYPE65", r"\# 3 010203"), Ok(ResourceRecordData::Unknown(65, bytes)) if bytes == [1, 2, 3]));
+    assert!(generic("A", r"\# 3 010203").is_err());
+    assert!(generic("A", r"\# 5 c000020100").is_err());
+    assert!(generic("MX", r"\# 2 000a").is_err());
+    assert!(generic("TYPE65", "\\# 2 a\u{e9}b").is_err());
+    assert!(generic("TYPE65", r"\# 1 +1").is_err());
+    let long = format!(r"\# 65536 {}", "00".repeat(65536));
+    assert!(generic("TYPE65", &long).is_err());
+}
+
+#[test]
+fn truncate_keeps_whole_rrsets_that_fit() {
+    let record = |owner: &str, data| ResourceRecord::new(owner.parse().unwrap(), Class::Internet, 300, data);
+    let mut message = decode(&packet(MX_RESPONSE)).unwrap();
+    message.answers.extend((0..3).map(|i| record("example.org", ResourceRecordData::A(IPV4(192, 0, 2, i)))));
+    message.answers.push(record("example.org", ResourceRecordData::TXT(vec![vec![b'x'; 200]])));
+    message.authoritative_records.push(record("example.org", ResourceRecordData::NS("ns.example.org".parse().unwrap())));
+    message.edns = Some(Edns::new(1232, false));
+    let rrsets = |records: &[ResourceRecord]| records.iter()
+        .map(|record| (record.name.to_string(), u16::from(ResourceRecordType::from(&record.data))))
+        .collect::<Vec<_>>();
+    let full = message.encoded_len();
+
+    for max_size in 0..=full {
+        let mut truncated = message.clone();
+        truncated.truncate(max_size);
+        let size = truncated.encoded_len();
+        let sections = [
+            (&message.answers, &truncated.answers),
+            (&message.authoritative_records, &truncated.authoritative_records),
+            (&message.additional_records, &truncated.additional_records),
+        ];
+        for (before, after) in sections {
+            let (before, after) = (rrsets(before), rrsets(after));
+            assert!(before.starts_with(&after), "{max_size}: records were reordered");
+            if let (Some(last), Some(next)) = (after.last(), before.get(after.len())) {
+                assert_ne!(last, next, "{max_size}: an RRset was split");
+            }
+        }
+        let dropped = truncated.answers.len() < message.answers.len()
+            || truncated.authoritative_records.len() < message.authoritative_records.len();
+        assert_eq!(truncated.flags.is_truncated(), dropped || size > max_size, "{max_size}");
+        if size > max_size {
+            assert_eq!(truncated.answers.len() + truncated.authoritative_records.len() + truncated.additional_records.len(), 0);
+        }
+        if max_size == full {
+            assert_eq!(size, full);
+        }
+
+        // Keeping the next RRset as well would not have fit.
+        let mut larger = truncated.clone();
+        let section = [
+            (&mut larger.answers, &message.answers),
+            (&mut larger.authoritative_records, &message.authoritative_records),
+            (&mut larger.additional_records, &message.additional_records),
+        ].into_iter().find(|(after, before)| after.len() < before.len());
+        if let Some((after, before)) = section {
+            let keys = rrsets(before);
+            let end = (after.len() + 1..=before.len()).find(|&j| j == before.len() || keys[j] != keys[after.len()]).unwrap();
+            *after = before[..end].to_vec();
+            assert!(larger.encoded_len() > max_size, "{max_size}: dropped an RRset that fits");
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, UdpSocket},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use miadon::{
+    cache::HostCache,
+    message::*,
+    server::Server,
+    streams::{SliceReader, VecWriter},
+    upstream::{SelectionPolicy, Upstream, UpstreamPool},
+};
+
+
+/// Starts a server answering `host.test A` from local data, with nothing
+/// upstream, and returns the address it serves TCP on.
+fn serve(tcp_idle_timeout: Duration, max_tcp_connections: usize) -> SocketAddr {
+    let mut cache = HostCache::new(0, 86400, 10800, 1000, 1 << 20);
+    cache.bind("host.test".parse().unwrap(), 60, vec![ResourceRecordData::A(IPV4(192, 0, 2, 1))]);
+    let nowhere = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let upstream = UpstreamPool::new(
+        vec![Upstream::new(nowhere, Duration::from_millis(10), 0)],
+        SelectionPolicy::Failover, 3, Duration::from_secs(60),
+    );
+    let server = Arc::new(Server::new(cache, upstream, tcp_idle_timeout, max_tcp_connections));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || server.serve_tcp(listener));
+    address
+}
+
+fn connect(address: SocketAddr) -> TcpStream {
+    let connection = TcpStream::connect(address).unwrap();
+    connection.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    connection
+}
+
+/// A query for `host.test A`, framed with its length.
+fn query(id: u16) -> Vec<u8> {
+    let message = Message::new(
+        id,
+        Flags::new(true, false, false, true, false, Operation::Query, ResponseCode::NoError),
+        vec![Question { name: "host.test".parse().unwrap(), typ: ResourceRecordType::A, class: Class::Internet }],
+        vec![], vec![], vec![]);
+    let mut buffer = VecWriter::new();
+    message.write_to_stream(&mut buffer);
+    [&(buffer.bytes().len() as u16).to_be_bytes()[..], buffer.bytes()].concat()
+}
+
+/// Reads one framed response, returning its ID after checking it answers
+/// `host.test A`.
+fn response(connection: &mut TcpStream) -> u16 {
+    let mut length = [0u8; 2];
+    connection.read_exact(&mut length).unwrap();
+    let mut bytes = vec![0u8; u16::from_be_bytes(length) as usize];
+    connection.read_exact(&mut bytes).unwrap();
+    let message = Message::read_from_stream(&mut SliceReader::new(&bytes)).unwrap();
+    assert!(matches!(message.answers[..], [ResourceRecord { data: ResourceRecordData::A(IPV4(192, 0, 2, 1)), .. }]));
+    message.id
+}
+
+/// Whether the server has closed `connection`, as seen by a read.
+fn is_closed(connection: &mut TcpStream) -> bool {
+    matches!(connection.read(&mut [0u8; 1]), Ok(0) | Err(_))
+}
+
+
+#[test]
+fn pipelined_queries_are_answered_in_order() {
+    let mut connection = connect(serve(Duration::from_secs(5), 8));
+    connection.write_all(&[query(1), query(2), query(3)].concat()).unwrap();
+    assert_eq!([(); 3].map(|_| response(&mut connection)), [1, 2, 3]);
+}
+
+#[test]
+fn messages_split_across_writes_are_framed() {
+    let mut connection = connect(serve(Duration::from_secs(5), 8));
+    connection.set_nodelay(true).unwrap();
+    let bytes = [query(7), query(8)].concat();
+    for chunk in [&bytes[..1], &bytes[1..10], &bytes[10..bytes.len() - 3], &bytes[bytes.len() - 3..]] {
+        connection.write_all(chunk).unwrap();
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(response(&mut connection), 7);
+    assert_eq!(response(&mut connection), 8);
+}
+
+#[test]
+fn idle_connections_are_closed() {
+    let mut connection = connect(serve(Duration::from_millis(200), 8));
+    connection.write_all(&query(1)).unwrap();
+    assert_eq!(response(&mut connection), 1);
+    let start = Instant::now();
+    assert!(is_closed(&mut connection));
+    let idle = start.elapsed();
+    assert!(idle >= Duration::from_millis(150) && idle < Duration::from_secs(2), "closed after {idle:?}");
+}
+
+#[test]
+fn connections_over_the_limit_are_closed() {
+    let address = serve(Duration::from_secs(5), 2);
+    let mut first = connect(address);
+    let mut second = connect(address);
+    first.write_all(&query(1)).unwrap();
+    second.write_all(&query(2)).unwrap();
+    assert_eq!(response(&mut first), 1);
+    assert_eq!(response(&mut second), 2);
+
+    let mut third = connect(address);
+    let _ = third.write_all(&query(3));
+    assert!(is_closed(&mut third));
+
+    // Once a connection closes, there is room for another.
+    drop(first);
+    let deadline = Instant::now() + Duration::from_secs(2);
+    loop {
+        let mut next = connect(address);
+        if next.write_all(&query(4)).is_ok() && !is_closed(&mut next) {
+            break;
+        }
+        assert!(Instant::now() < deadline, "no room after a connection closed");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,79 @@
+use miadon::{
+    error::DecodeError,
+    streams::{IStream, OStream, SliceReader, VecWriter},
+};
+
+
+#[test]
+fn slice_reader_reads_in_order() {
+    let bytes = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x61, 0x62];
+    let mut reader = SliceReader::new(&bytes);
+    assert_eq!(reader.read_u8().unwrap(), 0x01);
+    assert_eq!(reader.read_u16().unwrap(), 0x0203);
+    assert_eq!(reader.read_u32().unwrap(), 0x04050607);
+    assert_eq!(reader.position(), 7);
+    assert_eq!(reader.read_string(2).unwrap(), "ab");
+    assert!(matches!(reader.read_u8(), Err(DecodeError::Truncated)));
+}
+
+#[test]
+fn slice_reader_borrows_slices() {
+    let bytes = [1, 2, 3, 4, 5];
+    let mut reader = SliceReader::new(&bytes);
+    reader.read_u8().unwrap();
+    let slice = reader.read_slice(3).unwrap();
+    assert_eq!(slice, &bytes[1..4]);
+    assert!(std::ptr::eq(slice.as_ptr(), bytes[1..].as_ptr()));
+    assert_eq!(reader.position(), 4);
+    assert_eq!(reader.read_slice(0).unwrap(), &[] as &[u8]);
+}
+
+#[test]
+fn slice_reader_checks_bounds() {
+    let bytes = [1, 2, 3, 4];
+    let mut reader = SliceReader::new(&bytes);
+    reader.read_u8().unwrap();
+    for len in [4, 5, usize::MAX] {
+        assert!(matches!(reader.read_slice(len), Err(DecodeError::Truncated)));
+        assert!(matches!(reader.read_bytes(len), Err(DecodeError::Truncated)));
+        assert_eq!(reader.position(), 1);
+    }
+    assert_eq!(reader.read_bytes(3).unwrap(), [2, 3, 4]);
+    assert!(matches!(reader.read_u16(), Err(DecodeError::Truncated)));
+}
+
+#[test]
+fn slice_reader_seeks_within_the_message() {
+    let bytes = [1, 2, 3];
+    let mut reader = SliceReader::new(&bytes);
+    reader.seek(2).unwrap();
+    assert_eq!(reader.read_u8().unwrap(), 3);
+    reader.seek(0).unwrap();
+    assert_eq!(reader.read_u8().unwrap(), 1);
+    // Seeking to the end is allowed, but there is nothing left to read.
+    reader.seek(3).unwrap();
+    assert!(matches!(reader.read_u8(), Err(DecodeError::Truncated)));
+    assert!(matches!(reader.seek(4), Err(DecodeError::Truncated)));
+    assert_eq!(reader.position(), 3);
+}
+
+#[test]
+fn vec_writer_collects_bytes() {
+    let mut writer = VecWriter::new();
+    writer.write_u8(0x01);
+    writer.write_u16(0x0203);
+    writer.write_u32(0x04050607);
+    writer.write_bytes(&[0x08, 0x09]);
+    writer.write_string("ab");
+    assert_eq!(writer.bytes(), [1, 2, 3, 4, 5, 6, 7, 8, 9, b'a', b'b']);
+    assert_eq!(writer.position(), 11);
+}
+
+#[test]
+fn vec_writer_counts_positions_from_its_start() {
+    let mut writer = VecWriter::starting_at(12);
+    assert_eq!(writer.position(), 12);
+    writer.write_u16(0xc00c);
+    assert_eq!(writer.position(), 14);
+    assert_eq!(writer.bytes(), [0xc0, 0x0c]);
+}
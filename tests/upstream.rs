@@ -0,0 +1,170 @@
+use std::{
+    net::{SocketAddr, UdpSocket},
+    sync::{atomic::{AtomicUsize, Ordering}, Arc},
+    thread,
+    time::Duration,
+};
+
+use miadon::{
+    message::*,
+    udp::UDPStream,
+    upstream::{SelectionPolicy, Upstream, UpstreamPool},
+};
+
+
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+
+/// A resolver on 127.0.0.1 that answers every A query with `10.0.0.{tag}`
+/// after `delay`, or never answers if `tag` is `None`.
+struct StandIn {
+    address: SocketAddr,
+    queries: Arc<AtomicUsize>,
+}
+
+impl StandIn {
+    fn new(tag: Option<u8>, delay: Duration) -> Self {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counter = queries.clone();
+        thread::spawn(move || {
+            let mut stream = UDPStream::new(socket);
+            loop {
+                let (query, source) = stream.receive().unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                let (Some(tag), Ok(query)) = (tag, query) else {
+                    continue;
+                };
+                thread::sleep(delay);
+                let answers = query.questions.iter()
+                    .map(|question| ResourceRecord::new(question.name.clone(), Class::Internet, 60, ResourceRecordData::A(IPV4(10, 0, 0, tag))))
+                    .collect();
+                let response = Message::new(
+                    query.id,
+                    Flags::new(false, false, false, true, true, Operation::Query, ResponseCode::NoError),
+                    query.questions.clone(), answers, vec![], vec![]);
+                stream.send(&response, source).unwrap();
+            }
+        });
+        Self { address, queries }
+    }
+
+    fn answering(tag: u8) -> Self {
+        Self::new(Some(tag), Duration::ZERO)
+    }
+
+    fn silent() -> Self {
+        Self::new(None, Duration::ZERO)
+    }
+
+    fn queries(&self) -> usize {
+        self.queries.load(Ordering::SeqCst)
+    }
+}
+
+
+fn pool(stand_ins: &[&StandIn], policy: SelectionPolicy, failure_threshold: u32, probe_interval: Duration) -> UpstreamPool {
+    UpstreamPool::new(
+        stand_ins.iter().map(|stand_in| Upstream::new(stand_in.address, TIMEOUT, 0)).collect(),
+        policy, failure_threshold, probe_interval,
+    )
+}
+
+fn query() -> Message {
+    Message::new(
+        0x4242,
+        Flags::new(true, false, false, true, false, Operation::Query, ResponseCode::NoError),
+        vec![Question { name: "example.test".parse().unwrap(), typ: ResourceRecordType::A, class: Class::Internet }],
+        vec![], vec![], vec![])
+}
+
+/// Which stand-in answered, or `None` for a SERVFAIL.
+fn answered_by(pool: &UpstreamPool) -> Option<u8> {
+    let response = pool.forward(&query());
+    assert_eq!(response.id, 0x4242);
+    match response.answers.first().map(|record| &record.data) {
+        Some(ResourceRecordData::A(IPV4(10, 0, 0, tag))) => Some(*tag),
+        _ => {
+            assert!(matches!(response.flags.response_code(), ResponseCode::ServerFailure));
+            None
+        },
+    }
+}
+
+
+#[test]
+fn failover_tries_upstreams_in_order() {
+    let (silent, first, second) = (StandIn::silent(), StandIn::answering(1), StandIn::answering(2));
+    let pool = pool(&[&silent, &first, &second], SelectionPolicy::Failover, 100, Duration::from_secs(60));
+    assert_eq!(answered_by(&pool), Some(1));
+    assert_eq!(answered_by(&pool), Some(1));
+    assert_eq!((silent.queries(), first.queries(), second.queries()), (2, 2, 0));
+}
+
+#[test]
+fn round_robin_rotates_the_first_choice() {
+    let stand_ins = [StandIn::answering(1), StandIn::answering(2), StandIn::answering(3)];
+    let pool = pool(&stand_ins.iter().collect::<Vec<_>>(), SelectionPolicy::RoundRobin, 3, Duration::from_secs(60));
+    let answers: Vec<_> = (0..6).map(|_| answered_by(&pool)).collect();
+    assert_eq!(answers, [1, 2, 3, 1, 2, 3].map(Some));
+}
+
+#[test]
+fn random_spreads_queries() {
+    let (first, second) = (StandIn::answering(1), StandIn::answering(2));
+    let pool = pool(&[&first, &second], SelectionPolicy::Random, 3, Duration::from_secs(60));
+    for _ in 0..64 {
+        assert!(answered_by(&pool).is_some());
+    }
+    assert!(first.queries() > 0 && second.queries() > 0);
+    assert_eq!(first.queries() + second.queries(), 64);
+}
+
+#[test]
+fn fastest_prefers_the_lowest_round_trip_time() {
+    let slow = StandIn::new(Some(1), Duration::from_millis(40));
+    let fast = StandIn::answering(2);
+    let pool = pool(&[&slow, &fast], SelectionPolicy::Fastest, 3, Duration::from_secs(60));
+    // Each upstream is measured once before the fastest is preferred.
+    assert_eq!(answered_by(&pool), Some(1));
+    assert_eq!(answered_by(&pool), Some(2));
+    for _ in 0..4 {
+        assert_eq!(answered_by(&pool), Some(2));
+    }
+    assert_eq!(slow.queries(), 1);
+}
+
+#[test]
+fn upstreams_are_marked_down_after_repeated_timeouts() {
+    let (silent, backup) = (StandIn::silent(), StandIn::answering(1));
+    let pool = pool(&[&silent, &backup], SelectionPolicy::Failover, 2, Duration::from_secs(60));
+    assert_eq!(answered_by(&pool), Some(1));
+    assert_eq!(answered_by(&pool), Some(1));
+    assert_eq!(silent.queries(), 2);
+    for _ in 0..3 {
+        assert_eq!(answered_by(&pool), Some(1));
+    }
+    assert_eq!(silent.queries(), 2);
+}
+
+#[test]
+fn down_upstreams_are_probed_again_after_the_interval() {
+    let (silent, backup) = (StandIn::silent(), StandIn::answering(1));
+    let pool = pool(&[&silent, &backup], SelectionPolicy::Failover, 1, Duration::from_millis(300));
+    assert_eq!(answered_by(&pool), Some(1));
+    assert_eq!(answered_by(&pool), Some(1));
+    assert_eq!(silent.queries(), 1);
+    thread::sleep(Duration::from_millis(350));
+    assert_eq!(answered_by(&pool), Some(1));
+    assert_eq!(silent.queries(), 2);
+}
+
+#[test]
+fn down_upstreams_are_still_tried_last() {
+    let silent = StandIn::silent();
+    let pool = pool(&[&silent], SelectionPolicy::Failover, 1, Duration::from_secs(60));
+    assert_eq!(answered_by(&pool), None);
+    assert_eq!(answered_by(&pool), None);
+    assert_eq!(silent.queries(), 2);
+}
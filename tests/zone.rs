@@ -0,0 +1,166 @@
+use std::{fs, path::PathBuf, process};
+
+use miadon::{error::ZoneError, message::*, zone::Zone};
+
+
+/// Writes `files` to a fresh directory and loads the first as a zone for
+/// `example.test`.
+fn load(test: &str, files: &[(&str, &str)]) -> Result<Zone, ZoneError> {
+    let directory = std::env::temp_dir().join(format!("miadon-zone-{}-{test}", process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    for (file, text) in files {
+        fs::write(directory.join(file), text).unwrap();
+    }
+    let zone = Zone::load(&directory.join(files[0].0), "example.test".parse().unwrap());
+    fs::remove_dir_all(&directory).unwrap();
+    zone
+}
+
+/// Each record as `owner ttl type`, with its data in debug form.
+fn summary(zone: &Zone) -> Vec<String> {
+    zone.records.iter()
+        .map(|record| format!("{} {} {:?}", record.name, record.time_to_live, record.data))
+        .collect()
+}
+
+fn error_at(error: &ZoneError) -> (String, Option<usize>) {
+    (error.path.file_name().unwrap().to_string_lossy().into_owned(), error.line)
+}
+
+
+#[test]
+fn parses_entries_across_lines() {
+    let zone = load("lines", &[("example.zone", "\
+$TTL 1h30m
+@   IN  SOA ns1 hostmaster.example.test. (
+        2024010101 ; serial
+        2h 15m     ; refresh, retry
+        1w 300 )
+    IN  NS  ns1
+    NS  ns2.other.test.
+ns1 600 IN A 192.0.2.1
+    AAAA 2001:db8::1
+mx  MX  10 ns1
+")]).unwrap();
+    let records = summary(&zone);
+    assert_eq!(records.len(), 6);
+    assert!(records[0].starts_with("example.test. 5400 SOA("), "{}", records[0]);
+    let ResourceRecordData::SOA(soa) = &zone.records[0].data else { panic!() };
+    assert_eq!(soa.primary.to_string(), "ns1.example.test.");
+    assert_eq!(soa.mailbox.to_string(), "hostmaster.example.test.");
+    assert_eq!((soa.serial, soa.refresh, soa.retry, soa.expire, soa.minimum), (2024010101, 7200, 900, 604800, 300));
+    assert_eq!(zone.records[1].name.to_string(), "example.test.");
+    assert!(matches!(&zone.records[1].data, ResourceRecordData::NS(name) if name.to_string() == "ns1.example.test."));
+    assert!(matches!(&zone.records[2].data, ResourceRecordData::NS(name) if name.to_string() == "ns2.other.test."));
+    assert_eq!(zone.records[3].time_to_live, 600);
+    assert_eq!(zone.records[4].name.to_string(), "ns1.example.test.");
+    assert_eq!(zone.records[4].time_to_live, 5400);
+    assert!(matches!(&zone.records[5].data, ResourceRecordData::MX(mx) if mx.preference == 10 && mx.exchange.to_string() == "ns1.example.test."));
+}
+
+#[test]
+fn time_to_live_falls_back_to_the_last_one_given() {
+    let zone = load("ttl", &[("example.zone", "\
+@ SOA ns1 hostmaster 1 2 3 4 1d
+mail 2m A 192.0.2.2
+ftp A 192.0.2.3
+    AAAA 2001:db8::3
+")]).unwrap();
+    let ttls: Vec<u32> = zone.records.iter().map(|record| record.time_to_live).collect();
+    assert_eq!(ttls, [86400, 120, 120, 120]);
+}
+
+#[test]
+fn include_restores_origin_and_owner() {
+    let zone = load("include", &[
+        ("example.zone", "\
+$TTL 300
+www A 192.0.2.1
+$INCLUDE sub.inc sub
+    AAAA ::1
+host A 192.0.2.3
+"),
+        ("sub.inc", "host A 192.0.2.2\n@ TXT sub\n"),
+    ]).unwrap();
+    let owners: Vec<String> = zone.records.iter().map(|record| record.name.to_string()).collect();
+    assert_eq!(owners, [
+        "www.example.test.", "host.sub.example.test.", "sub.example.test.", "www.example.test.", "host.example.test.",
+    ]);
+}
+
+#[test]
+fn origin_applies_to_later_relative_names() {
+    let zone = load("origin", &[("example.zone", "\
+$TTL 300
+$ORIGIN other.test.
+a A 192.0.2.1
+$ORIGIN sub
+b A 192.0.2.2
+")]).unwrap();
+    let owners: Vec<String> = zone.records.iter().map(|record| record.name.to_string()).collect();
+    assert_eq!(owners, ["a.other.test.", "b.sub.other.test."]);
+}
+
+#[test]
+fn escapes_in_strings_and_names() {
+    let zone = load("escapes", &[("example.zone", r#"$TTL 300
+txt TXT "hello world" "say \"hi\"; ok" \065BC
+a\046b\032c A 192.0.2.1
+"#)]).unwrap();
+    let ResourceRecordData::TXT(strings) = &zone.records[0].data else { panic!() };
+    assert_eq!(strings, &[b"hello world".to_vec(), b"say \"hi\"; ok".to_vec(), b"ABC".to_vec()]);
+    assert_eq!(zone.records[1].name.0[0].as_bytes(), b"a.b c");
+    assert_eq!(zone.records[1].name.to_string(), r"a\.b\032c.example.test.");
+}
+
+#[test]
+fn generic_data_is_accepted() {
+    let zone = load("generic", &[("example.zone", "$TTL 300\nx TYPE65 \\# 3 010203\ny A \\# 4 c0000201\n")]).unwrap();
+    assert!(matches!(&zone.records[0].data, ResourceRecordData::Unknown(65, bytes) if bytes == &[1, 2, 3]));
+    assert!(matches!(zone.records[1].data, ResourceRecordData::A(IPV4(192, 0, 2, 1))));
+}
+
+#[test]
+fn errors_carry_the_line_of_the_entry() {
+    let cases = [
+        ("$TTL 300\nwww A 192.0.2.1\nwww BOGUS data\n", 3),
+        ("$TTL 300\n\nwww A 192.0.2.300\n", 3),
+        ("$TTL 300\n@ SOA ns1 hostmaster (\n 1 2 3 4\n", 2),
+        ("$TTL 300\nwww A 192.0.2.1 )\n", 2),
+        ("$TTL 300\ntxt TXT \"unterminated\n", 2),
+        ("www A 192.0.2.1\n", 1),
+        ("$TTL 300\n  A 192.0.2.1\n", 2),
+        ("$TTL 300\nwww CH A 192.0.2.1\n", 2),
+        ("$TTL 300\n$BOGUS x\n", 2),
+        ("$TTL 300\nwww A 192.0.2.1\nx TYPE65 \\# 2 a\u{e9}b\n", 3),
+        ("$TTL 300\nwww TXT \"ok\"\nlong.", 3),
+    ];
+    for (text, line) in cases {
+        let text = text.replace("long.", &format!("{} A 192.0.2.1", "a".repeat(64)));
+        let error = load("errors", &[("example.zone", &text)]).err()
+            .unwrap_or_else(|| panic!("{text:?} should not load"));
+        assert_eq!(error_at(&error), ("example.zone".into(), Some(line)), "{text:?}: {error}");
+    }
+}
+
+#[test]
+fn errors_in_included_files_name_that_file() {
+    let error = load("include-error", &[
+        ("example.zone", "$TTL 300\nwww A 192.0.2.1\n$INCLUDE sub.inc\n"),
+        ("sub.inc", "ok A 192.0.2.2\n\nbad MX 10\n"),
+    ]).err().unwrap();
+    assert_eq!(error_at(&error), ("sub.inc".into(), Some(3)));
+
+    let error = load("include-missing", &[("example.zone", "$TTL 300\n$INCLUDE missing.inc\n")]).err().unwrap();
+    assert_eq!(error_at(&error), ("missing.inc".into(), None));
+
+    let error = load("include-loop", &[("example.zone", "$TTL 300\n$INCLUDE example.zone\n")]).err().unwrap();
+    assert_eq!(error_at(&error), ("example.zone".into(), Some(2)));
+    assert!(error.message.contains("nested too deeply"), "{error}");
+}
+
+#[test]
+fn missing_files_have_no_line() {
+    let error = Zone::load(&PathBuf::from("/nonexistent/miadon.zone"), "example.test".parse().unwrap()).err().unwrap();
+    assert_eq!(error.line, None);
+}